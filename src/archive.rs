@@ -0,0 +1,89 @@
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+// roms can be loaded straight out of .zip and .gz archives
+//
+// a zip archive can hold several files, the first .nes, .fds or .unf entry is loaded unless one is
+// named after a #, e.g. roms.zip#game.nes
+const ZIP_SIGNATURE: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+const GZIP_SIGNATURE: [u8; 2] = [0x1f, 0x8b];
+
+const ROM_EXTENSIONS: [&str; 3] = ["nes", "fds", "unf"];
+
+// the rom's contents and the path the cartridge should see, which is the archive's directory with
+// the rom's own file name so save files land next to the archive
+pub fn read_rom(path: &str) -> (Vec<u8>, String) {
+    let (file_path, entry) = match path.rfind('#') {
+        Some(i) if path[..i].to_lowercase().ends_with(".zip") => (&path[..i], Some(&path[i + 1..])),
+        _ => (path, None)
+    };
+
+    let buffer = match fs::read(file_path) {
+        Ok(b) => b,
+        Err(_) => panic!("Cannot load rom! {}", file_path)
+    };
+
+    if buffer.starts_with(&ZIP_SIGNATURE) {
+        let (buffer, name) = read_zip(buffer, file_path, entry);
+        (buffer, next_to(file_path, &name))
+    } else if buffer.starts_with(&GZIP_SIGNATURE) {
+        let mut rom = Vec::new();
+        if let Err(e) = GzDecoder::new(&buffer[..]).read_to_end(&mut rom) {
+            panic!("Cannot decompress rom! {} {}", file_path, e);
+        }
+
+        // game.nes.gz holds game.nes
+        let name = Path::new(file_path).file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        (rom, next_to(file_path, &name))
+    } else {
+        (buffer, String::from(file_path))
+    }
+}
+
+fn read_zip(buffer: Vec<u8>, file_path: &str, entry: Option<&str>) -> (Vec<u8>, String) {
+    let mut archive = match ZipArchive::new(Cursor::new(buffer)) {
+        Ok(archive) => archive,
+        Err(e) => panic!("Cannot open zip archive! {} {}", file_path, e)
+    };
+
+    let is_rom = |name: &str| {
+        let extension = Path::new(name).extension().unwrap_or_default().to_string_lossy().to_lowercase();
+        ROM_EXTENSIONS.contains(&extension.as_str())
+    };
+
+    let name = match entry {
+        Some(entry) => String::from(entry),
+        None => {
+            // in the order they're stored in the archive
+            let name = (0..archive.len())
+                .filter_map(|i| archive.by_index(i).ok().map(|file| String::from(file.name())))
+                .find(|name| is_rom(name));
+            match name {
+                Some(name) => name,
+                None => panic!("No .nes, .fds or .unf file in zip archive! {}", file_path)
+            }
+        }
+    };
+
+    let mut file = match archive.by_name(&name) {
+        Ok(file) => file,
+        Err(_) => panic!("Cannot find {} in zip archive! {}", name, file_path)
+    };
+
+    let mut rom = Vec::new();
+    if let Err(e) = file.read_to_end(&mut rom) {
+        panic!("Cannot decompress {} in zip archive! {} {}", name, file_path, e);
+    }
+
+    (rom, name)
+}
+
+// entries can be in folders inside the archive, only their file name is kept
+fn next_to(archive_path: &str, name: &str) -> String {
+    let name = Path::new(name).file_name().unwrap_or_default();
+    Path::new(archive_path).with_file_name(name).to_string_lossy().into_owned()
+}
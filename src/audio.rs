@@ -0,0 +1,62 @@
+// turns the per cpu cycle audio level into samples at the output rate
+//
+// the level is averaged over every output sample's worth of cpu cycles, which is a cheap low pass
+// filter that keeps the high pitched expansion channels from aliasing. a high pass filter then
+// removes the dc offset, like the capacitors on the console's audio output
+pub const SAMPLE_RATE: u32 = 44100;
+
+pub struct Mixer {
+    samples: Vec<f32>,
+
+    cycles_per_sample: f64,
+    cycles: f64,
+    sum: f32,
+    count: u32,
+
+    // previous input and output of the high pass filter
+    filter_input: f32,
+    filter_output: f32
+}
+
+impl Mixer {
+    pub fn new(cpu_frequency: f64, sample_rate: u32) -> Mixer {
+        Mixer {
+            samples: Vec::with_capacity(sample_rate as usize / 30),
+
+            cycles_per_sample: cpu_frequency / sample_rate as f64,
+            cycles: 0.0,
+            sum: 0.0,
+            count: 0,
+
+            filter_input: 0.0,
+            filter_output: 0.0
+        }
+    }
+
+    // called once per cpu cycle with the combined level of every channel
+    pub fn push(&mut self, level: f32) {
+        self.sum += level;
+        self.count += 1;
+        self.cycles += 1.0;
+
+        if self.cycles >= self.cycles_per_sample {
+            self.cycles -= self.cycles_per_sample;
+
+            let average = self.sum / self.count as f32;
+            self.sum = 0.0;
+            self.count = 0;
+
+            // ~90 Hz first order high pass
+            let output = 0.987 * (self.filter_output + average - self.filter_input);
+            self.filter_input = average;
+            self.filter_output = output;
+
+            self.samples.push(output);
+        }
+    }
+
+    // hand over every sample generated since the last call
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
@@ -0,0 +1,234 @@
+use crate::cartridge::Mirror;
+use crate::region::Region;
+
+use std::fs;
+
+// header database for correcting iNES 1.0 headers
+//
+// https://wiki.nesdev.com/w/index.php/NES_2.0_XML_Database
+//
+// games are looked up by the CRC-32/SHA-1 of their prg and chr rom in the same XML layout as the
+// NES 2.0 database, one <game> element per rom:
+//
+// <game>
+//   <!-- name of the rom -->
+//   <rom size="40960" crc32="..." sha1="..."/>
+//   <pcb mapper="0" submapper="0" mirroring="V" battery="0"/>
+//   <prgram size="8192"/>
+//   <console type="0" region="0"/>
+// </game>
+//
+// znes doesn't ship the NES 2.0 database, correcting headers needs it to be given with --db. the
+// embedded one is only for verified entries on top of that
+//
+// TODO - the embedded database is still empty, it needs the known bad dumps with their CRC-32 and
+// SHA-1 taken from the NES 2.0 database, so iNES 1.0 headers aren't corrected without --db yet
+const EMBEDDED_DATABASE: &str = include_str!("nes20db.xml");
+
+pub struct GameInfo {
+    pub name: String,
+    crc32: Option<u32>,
+    sha1: Option<[u8; 20]>,
+
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirror: Option<Mirror>,
+    pub battery: Option<bool>,
+    pub prg_ram_size: Option<usize>, // <prgram> and <prgnvram> together
+    pub region: Option<Region>
+}
+
+pub struct Database {
+    games: Vec<GameInfo>
+}
+
+impl Database {
+    // entries from the given file take priority over the embedded ones
+    pub fn load(file_path: Option<&str>) -> Database {
+        let mut games = Vec::new();
+
+        if let Some(file_path) = file_path {
+            let text = match fs::read_to_string(file_path) {
+                Ok(text) => text,
+                Err(_) => panic!("Cannot load header database! {}", file_path)
+            };
+            games.extend(parse(&text));
+        }
+        games.extend(parse(EMBEDDED_DATABASE));
+
+        Database {
+            games: games
+        }
+    }
+
+    // entries with a SHA-1 have to match it, the CRC-32 is only used on its own for entries
+    // without one
+    pub fn find(&self, crc32: u32, sha1: &[u8; 20]) -> Option<&GameInfo> {
+        self.games.iter().find(|game| match game.sha1 {
+            Some(game_sha1) => &game_sha1 == sha1,
+            None => game.crc32 == Some(crc32)
+        })
+    }
+}
+
+fn parse(text: &str) -> Vec<GameInfo> {
+    let mut games = Vec::new();
+
+    for game in text.split("<game>").skip(1) {
+        let game = game.split("</game>").next().unwrap_or("");
+
+        let crc32 = attribute(game, "rom", "crc32").and_then(|crc| u32::from_str_radix(crc, 16).ok());
+        let sha1 = attribute(game, "rom", "sha1").and_then(parse_sha1);
+        if crc32.is_none() && sha1.is_none() {
+            continue;
+        }
+
+        let name = match (game.find("<!--"), game.find("-->")) {
+            (Some(start), Some(end)) if start < end => game[start + 4..end].trim(),
+            _ => "unknown game"
+        };
+
+        let prg_ram = attribute(game, "prgram", "size").and_then(|size| size.parse::<usize>().ok());
+        let prg_nvram = attribute(game, "prgnvram", "size").and_then(|size| size.parse::<usize>().ok());
+        let prg_ram_size = match (prg_ram, prg_nvram) {
+            (None, None) => None,
+            _ => Some(prg_ram.unwrap_or(0) + prg_nvram.unwrap_or(0))
+        };
+
+        games.push(GameInfo {
+            name: String::from(name),
+            crc32: crc32,
+            sha1: sha1,
+
+            mapper: attribute(game, "pcb", "mapper").and_then(|mapper| mapper.parse().ok()),
+            submapper: attribute(game, "pcb", "submapper").and_then(|submapper| submapper.parse().ok()),
+            mirror: attribute(game, "pcb", "mirroring").and_then(|mirroring| match mirroring {
+                "H" => Some(Mirror::Horizontal),
+                "V" => Some(Mirror::Vertical),
+                _ => None
+            }),
+            battery: attribute(game, "pcb", "battery").map(|battery| battery == "1"),
+            prg_ram_size: prg_ram_size,
+            // same values as byte 12 of NES 2.0 headers, multi-region games run as NTSC
+            region: attribute(game, "console", "region").and_then(|region| match region {
+                "0" | "2" => Some(Region::NTSC),
+                "1" => Some(Region::PAL),
+                "3" => Some(Region::Dendy),
+                _ => None
+            })
+        });
+    }
+
+    games
+}
+
+// the value of name="..." in the first <tag .../> element
+fn attribute<'a>(game: &'a str, tag: &str, name: &str) -> Option<&'a str> {
+    let start = game.find(&format!("<{} ", tag))?;
+    let element = &game[start..];
+    let element = &element[..element.find('>')?];
+
+    let pattern = format!(" {}=\"", name);
+    let value = &element[element.find(&pattern)? + pattern.len()..];
+    Some(&value[..value.find('"')?])
+}
+
+fn parse_sha1(text: &str) -> Option<[u8; 20]> {
+    if text.len() != 40 {
+        return None;
+    }
+
+    let mut sha1 = [0; 20];
+    for (i, byte) in sha1.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(sha1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAMES: &str = r#"
+<database>
+<game>
+  <!-- Test Game (USA) -->
+  <rom size="40960" crc32="3B7A0C2D" sha1="0123456789ABCDEF0123456789abcdef01234567"/>
+  <pcb mapper="4" submapper="1" mirroring="V" battery="1"/>
+  <prgram size="2048"/>
+  <prgnvram size="8192"/>
+  <console type="0" region="1"/>
+</game>
+<game>
+  <rom size="24576" crc32="DEADBEEF"/>
+  <pcb mapper="0" mirroring="4" battery="0"/>
+  <console type="0" region="2"/>
+</game>
+<game>
+  <pcb mapper="1"/>
+</game>
+</database>
+"#;
+
+    fn database() -> Database {
+        Database {
+            games: parse(GAMES)
+        }
+    }
+
+    #[test]
+    fn parses_every_field() {
+        let games = parse(GAMES);
+        // the last game has no checksums, so it can never be found
+        assert_eq!(games.len(), 2);
+
+        let game = &games[0];
+        assert_eq!(game.name, "Test Game (USA)");
+        assert_eq!(game.crc32, Some(0x3b7a0c2d));
+        assert_eq!(game.mapper, Some(4));
+        assert_eq!(game.submapper, Some(1));
+        assert_eq!(game.mirror, Some(Mirror::Vertical));
+        assert_eq!(game.battery, Some(true));
+        assert_eq!(game.prg_ram_size, Some(0x2800));
+        assert_eq!(game.region, Some(Region::PAL));
+
+        let game = &games[1];
+        assert_eq!(game.name, "unknown game");
+        assert_eq!(game.submapper, None);
+        // four screen mirroring is left to the header
+        assert_eq!(game.mirror, None);
+        assert_eq!(game.battery, Some(false));
+        assert_eq!(game.prg_ram_size, None);
+        assert_eq!(game.region, Some(Region::NTSC));
+    }
+
+    #[test]
+    fn finds_games_by_checksum() {
+        let database = database();
+        let sha1 = parse_sha1("0123456789abcdef0123456789abcdef01234567").unwrap();
+
+        assert_eq!(database.find(0, &sha1).map(|game| game.mapper), Some(Some(4)));
+        assert_eq!(database.find(0xdeadbeef, &[0; 20]).map(|game| game.mapper), Some(Some(0)));
+        // a matching CRC-32 isn't enough when the entry has a SHA-1
+        assert!(database.find(0x3b7a0c2d, &[0; 20]).is_none());
+    }
+
+    #[test]
+    fn reads_attributes_of_the_right_element() {
+        let game = r#"<rom size="1" crc32="AB"/><pcb mapper="2" submapper="0"/>"#;
+
+        assert_eq!(attribute(game, "pcb", "mapper"), Some("2"));
+        assert_eq!(attribute(game, "pcb", "size"), None);
+        assert_eq!(attribute(game, "prgram", "size"), None);
+        // the name has to match exactly, not just the end of another attribute
+        assert_eq!(attribute(game, "rom", "c32"), None);
+        assert_eq!(attribute(r#"<pcb submapper="1" mapper="2"/>"#, "pcb", "mapper"), Some("2"));
+    }
+
+    #[test]
+    fn refuses_malformed_sha1() {
+        assert!(parse_sha1("0123").is_none());
+        assert!(parse_sha1(&"zz".repeat(20)).is_none());
+        assert_eq!(parse_sha1(&"ff".repeat(20)), Some([0xff; 20]));
+    }
+}
@@ -0,0 +1,296 @@
+// serial EEPROMs used for saving on Bandai boards
+//
+// https://wiki.nesdev.com/w/index.php/Bandai_FCG_board#Serial_EEPROM
+//
+// the game bit-bangs the I2C bus through a mapper register, driving the clock (SCL) and data
+// (SDA) lines directly. a transfer starts with SDA falling while SCL is high and ends with SDA
+// rising while SCL is high, in between data bits are sampled on the rising edge of SCL and every
+// byte is followed by an acknowledge bit from the receiver.
+//
+// the 24C02 (256 bytes) follows the standard protocol: a device select byte ($A0 | read bit), a
+// word address and then data. the older X24C01 (128 bytes) skips the device select, takes the
+// 7-bit word address and read bit in one byte, and sends everything least significant bit first
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum EepromKind {
+    X24C01,
+    X24C02
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Phase {
+    Idle,
+    Device, // receiving the device select byte
+    Address, // receiving the word address
+    Write, // receiving data
+    Read // sending data
+}
+
+pub struct Eeprom {
+    kind: EepromKind,
+    pub data: Vec<u8>,
+
+    scl: bool,
+    sda: bool,
+    output: bool, // the eeprom's side of SDA, it can only pull the line low
+
+    phase: Phase,
+    next_phase: Phase, // entered after the acknowledge bit
+    acknowledge: bool,
+    bit: u8, // bits of the current byte transferred so far, 8 during the acknowledge bit
+    shift: u8,
+    address: usize
+}
+
+impl Eeprom {
+    pub fn new(kind: EepromKind) -> Eeprom {
+        let size = match kind {
+            EepromKind::X24C01 => 0x80,
+            EepromKind::X24C02 => 0x100
+        };
+
+        Eeprom {
+            kind: kind,
+            data: vec![0xff; size],
+
+            scl: false,
+            sda: false,
+            output: true,
+
+            phase: Phase::Idle,
+            next_phase: Phase::Idle,
+            acknowledge: false,
+            bit: 0,
+            shift: 0,
+            address: 0
+        }
+    }
+
+    pub fn output(&self) -> bool {
+        self.output
+    }
+
+    // update both lines at once, as the mapper register does
+    pub fn write(&mut self, scl: bool, sda: bool) {
+        if self.scl && scl && self.sda != sda {
+            if sda {
+                // stop
+                self.phase = Phase::Idle;
+                self.output = true;
+            } else {
+                // start, or a repeated start which switches a 24C02 to reading
+                self.phase = match self.kind {
+                    EepromKind::X24C01 => Phase::Address,
+                    EepromKind::X24C02 => Phase::Device
+                };
+                self.bit = 0;
+                self.shift = 0;
+            }
+        } else if !self.scl && scl {
+            self.clock_rising(sda);
+        } else if self.scl && !scl {
+            self.clock_falling();
+        }
+
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    fn clock_rising(&mut self, sda: bool) {
+        match self.phase {
+            Phase::Idle => (),
+            Phase::Read => {
+                if self.bit < 8 {
+                    self.bit += 1;
+                } else {
+                    // the master acknowledges to keep reading, or stops
+                    self.bit = 0;
+                    self.address = (self.address + 1) % self.data.len();
+                    if sda {
+                        self.phase = Phase::Idle;
+                    }
+                }
+            },
+            _ => {
+                if self.bit < 8 {
+                    self.shift = (self.shift << 1) | sda as u8;
+                    self.bit += 1;
+
+                    if self.bit == 8 {
+                        self.receive_byte();
+                    }
+                } else {
+                    self.bit = 0;
+                    self.shift = 0;
+                    self.phase = self.next_phase;
+                }
+            }
+        }
+    }
+
+    // the eeprom changes its output while the clock is low
+    fn clock_falling(&mut self) {
+        self.output = match self.phase {
+            Phase::Read if self.bit < 8 => {
+                let byte = self.data[self.address];
+                match self.kind {
+                    EepromKind::X24C01 => byte & (1 << self.bit) != 0,
+                    EepromKind::X24C02 => byte & (0x80 >> self.bit) != 0
+                }
+            },
+            Phase::Device | Phase::Address | Phase::Write if self.bit == 8 => !self.acknowledge,
+            _ => true
+        };
+    }
+
+    fn receive_byte(&mut self) {
+        let byte = match self.kind {
+            EepromKind::X24C01 => self.shift.reverse_bits(),
+            EepromKind::X24C02 => self.shift
+        };
+
+        self.acknowledge = true;
+        match (self.kind, self.phase) {
+            // 1010 xxxR
+            (EepromKind::X24C02, Phase::Device) => {
+                if byte & 0xf0 == 0xa0 {
+                    self.next_phase = if byte & 1 != 0 { Phase::Read } else { Phase::Address };
+                } else {
+                    self.acknowledge = false;
+                    self.next_phase = Phase::Idle;
+                }
+            },
+            // RAAA AAAA, read bit and address
+            (EepromKind::X24C01, Phase::Address) => {
+                self.address = (byte & 0x7f) as usize;
+                self.next_phase = if byte & 0x80 != 0 { Phase::Read } else { Phase::Write };
+            },
+            (EepromKind::X24C02, Phase::Address) => {
+                self.address = byte as usize;
+                self.next_phase = Phase::Write;
+            },
+            _ => {
+                self.data[self.address] = byte;
+                self.address = (self.address + 1) % self.data.len();
+                self.next_phase = Phase::Write;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the game's side of the bus, every helper leaves SCL low except stop
+
+    fn start(eeprom: &mut Eeprom) {
+        eeprom.write(false, true);
+        eeprom.write(true, true);
+        eeprom.write(true, false);
+        eeprom.write(false, false);
+    }
+
+    fn stop(eeprom: &mut Eeprom) {
+        eeprom.write(false, false);
+        eeprom.write(true, false);
+        eeprom.write(true, true);
+    }
+
+    fn send_bit(eeprom: &mut Eeprom, bit: bool) {
+        eeprom.write(false, bit);
+        eeprom.write(true, bit);
+        eeprom.write(false, bit);
+    }
+
+    // returns whether the eeprom acknowledged the byte
+    fn send_byte(eeprom: &mut Eeprom, byte: u8, lsb_first: bool) -> bool {
+        for i in 0..8 {
+            let bit = if lsb_first { byte & (1 << i) } else { byte & (0x80 >> i) };
+            send_bit(eeprom, bit != 0);
+        }
+
+        let acknowledged = !eeprom.output();
+        send_bit(eeprom, true);
+        acknowledged
+    }
+
+    // the game acknowledges every byte but the last
+    fn read_byte(eeprom: &mut Eeprom, lsb_first: bool, last: bool) -> u8 {
+        let mut byte = 0;
+        for i in 0..8 {
+            if eeprom.output() {
+                byte |= if lsb_first { 1 << i } else { 0x80 >> i };
+            }
+            eeprom.write(true, true);
+            eeprom.write(false, true);
+        }
+
+        send_bit(eeprom, last);
+        byte
+    }
+
+    #[test]
+    fn x24c02_writes_and_reads_sequentially() {
+        let mut eeprom = Eeprom::new(EepromKind::X24C02);
+
+        start(&mut eeprom);
+        assert!(send_byte(&mut eeprom, 0xa0, false));
+        assert!(send_byte(&mut eeprom, 0x10, false));
+        assert!(send_byte(&mut eeprom, 0x12, false));
+        assert!(send_byte(&mut eeprom, 0x34, false));
+        stop(&mut eeprom);
+        assert_eq!(eeprom.data[0x10..0x12], [0x12, 0x34]);
+
+        // set the address with a write, then switch to reading with a repeated start
+        start(&mut eeprom);
+        assert!(send_byte(&mut eeprom, 0xa0, false));
+        assert!(send_byte(&mut eeprom, 0x10, false));
+        start(&mut eeprom);
+        assert!(send_byte(&mut eeprom, 0xa1, false));
+        assert_eq!(read_byte(&mut eeprom, false, false), 0x12);
+        assert_eq!(read_byte(&mut eeprom, false, true), 0x34);
+        stop(&mut eeprom);
+        assert!(eeprom.output());
+    }
+
+    #[test]
+    fn x24c02_ignores_other_devices() {
+        let mut eeprom = Eeprom::new(EepromKind::X24C02);
+
+        start(&mut eeprom);
+        assert!(!send_byte(&mut eeprom, 0xb0, false));
+        send_byte(&mut eeprom, 0x00, false);
+        send_byte(&mut eeprom, 0x55, false);
+        stop(&mut eeprom);
+        assert!(eeprom.data.iter().all(|&byte| byte == 0xff));
+    }
+
+    #[test]
+    fn x24c01_takes_the_address_first_and_least_significant_bit_first() {
+        let mut eeprom = Eeprom::new(EepromKind::X24C01);
+        assert_eq!(eeprom.data.len(), 0x80);
+
+        start(&mut eeprom);
+        assert!(send_byte(&mut eeprom, 0x05, true));
+        assert!(send_byte(&mut eeprom, 0x5a, true));
+        stop(&mut eeprom);
+        assert_eq!(eeprom.data[5], 0x5a);
+
+        start(&mut eeprom);
+        assert!(send_byte(&mut eeprom, 0x80 | 0x05, true));
+        assert_eq!(read_byte(&mut eeprom, true, true), 0x5a);
+        stop(&mut eeprom);
+    }
+
+    #[test]
+    fn addresses_wrap_around() {
+        let mut eeprom = Eeprom::new(EepromKind::X24C01);
+
+        start(&mut eeprom);
+        send_byte(&mut eeprom, 0x7f, true);
+        send_byte(&mut eeprom, 0x01, true);
+        send_byte(&mut eeprom, 0x02, true);
+        stop(&mut eeprom);
+        assert_eq!((eeprom.data[0x7f], eeprom.data[0]), (0x01, 0x02));
+    }
+}
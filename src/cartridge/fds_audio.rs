@@ -0,0 +1,290 @@
+// Famicom Disk System audio, a wavetable channel with a frequency modulator
+//
+// https://wiki.nesdev.com/w/index.php/FDS_audio
+//
+// the channel steps through 64 6-bit samples written by the cpu, its pitch is bent by the
+// modulator which steps through a table of 64 3-bit adjustments to a 7-bit signed counter. both
+// have a volume/gain envelope clocked by a shared, programmable divider
+pub struct FdsAudio {
+    wave_table: [u8; 64],
+    wave_write_enabled: bool, // also holds the output at the current level
+    wave_frequency: u16,
+    wave_accumulator: u32,
+    wave_position: usize,
+    wave_halted: bool,
+
+    mod_table: [u8; 64],
+    mod_frequency: u16,
+    mod_accumulator: u32,
+    mod_position: usize,
+    mod_counter: i32, // 7-bit signed
+    mod_halted: bool,
+    pitch_bend: i32, // the modulator's current adjustment to the wave frequency
+
+    volume: Envelope,
+    modulation: Envelope,
+    envelopes_disabled: bool,
+    envelope_speed: u8, // shared by both envelopes
+
+    master_volume: u8,
+    output: u8
+}
+
+struct Envelope {
+    disabled: bool, // the gain is set directly
+    increase: bool,
+    speed: u8,
+    gain: u8,
+    timer: u32
+}
+
+// volume of the four master volume settings, 2/2, 2/3, 2/4 and 2/5 scaled by 36
+const MASTER_VOLUMES: [u32; 4] = [36, 24, 17, 14];
+
+// modulator counter adjustment for each table entry, 4 resets the counter
+const MOD_ADJUSTMENTS: [i32; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            disabled: true,
+            increase: false,
+            speed: 0,
+            gain: 0,
+            timer: 0
+        }
+    }
+
+    // MDSS SSSS, mode (1: direct gain), direction (1: increase), speed or gain
+    fn write(&mut self, value: u8, envelope_speed: u8) {
+        self.disabled = value & 0x80 != 0;
+        self.increase = value & 0x40 != 0;
+        self.speed = value & 0x3f;
+        if self.disabled {
+            self.gain = value & 0x3f;
+        }
+
+        self.reset_timer(envelope_speed);
+    }
+
+    fn reset_timer(&mut self, envelope_speed: u8) {
+        self.timer = 8 * (self.speed as u32 + 1) * envelope_speed as u32;
+    }
+
+    // true if the gain changed
+    fn clock(&mut self, envelope_speed: u8) -> bool {
+        if self.disabled || envelope_speed == 0 {
+            return false;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return false;
+        }
+
+        self.reset_timer(envelope_speed);
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+
+        true
+    }
+}
+
+impl FdsAudio {
+    pub fn new() -> FdsAudio {
+        FdsAudio {
+            wave_table: [0; 64],
+            wave_write_enabled: false,
+            wave_frequency: 0,
+            wave_accumulator: 0,
+            wave_position: 0,
+            wave_halted: true,
+
+            mod_table: [0; 64],
+            mod_frequency: 0,
+            mod_accumulator: 0,
+            mod_position: 0,
+            mod_counter: 0,
+            mod_halted: true,
+            pitch_bend: 0,
+
+            volume: Envelope::new(),
+            modulation: Envelope::new(),
+            envelopes_disabled: false,
+            envelope_speed: 0xe8,
+
+            master_volume: 0,
+            output: 0
+        }
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            // the wave table reads back the sample being played while it's writable
+            0x4040..=0x407f => {
+                if self.wave_write_enabled {
+                    self.wave_table[address - 0x4040]
+                } else {
+                    self.wave_table[self.wave_position]
+                }
+            },
+            0x4090 => self.volume.gain,
+            0x4092 => self.modulation.gain,
+            _ => 0
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x4040..=0x407f if self.wave_write_enabled => self.wave_table[address - 0x4040] = value & 0x3f,
+            0x4080 => self.volume.write(value, self.envelope_speed),
+            0x4082 => {
+                self.wave_frequency = (self.wave_frequency & 0x0f00) | value as u16;
+                self.update_pitch_bend();
+            },
+            // HE-- FFFF, halt the wave, disable the envelopes, frequency high
+            0x4083 => {
+                self.wave_frequency = (self.wave_frequency & 0x00ff) | (((value & 0x0f) as u16) << 8);
+                self.wave_halted = value & 0x80 != 0;
+                self.envelopes_disabled = value & 0x40 != 0;
+                self.update_pitch_bend();
+
+                if self.wave_halted {
+                    self.wave_accumulator = 0;
+                    self.wave_position = 0;
+                }
+                if self.envelopes_disabled {
+                    self.volume.reset_timer(self.envelope_speed);
+                    self.modulation.reset_timer(self.envelope_speed);
+                }
+            },
+            0x4084 => {
+                self.modulation.write(value, self.envelope_speed);
+                self.update_pitch_bend();
+            },
+            0x4085 => {
+                self.set_mod_counter((value & 0x7f) as i32);
+                self.update_pitch_bend();
+            },
+            0x4086 => self.mod_frequency = (self.mod_frequency & 0x0f00) | value as u16,
+            // H--- FFFF, halt the modulator, frequency high
+            0x4087 => {
+                self.mod_frequency = (self.mod_frequency & 0x00ff) | (((value & 0x0f) as u16) << 8);
+                self.mod_halted = value & 0x80 != 0;
+                if self.mod_halted {
+                    self.mod_accumulator = 0;
+                }
+            },
+            // every write fills two entries of the table, only while the modulator is halted
+            0x4088 if self.mod_halted => {
+                self.mod_table[self.mod_position] = value & 7;
+                self.mod_table[(self.mod_position + 1) & 0x3f] = value & 7;
+                self.mod_position = (self.mod_position + 2) & 0x3f;
+            },
+            // W--- --VV, wave table write enable, master volume
+            0x4089 => {
+                self.wave_write_enabled = value & 0x80 != 0;
+                self.master_volume = value & 3;
+            },
+            0x408a => {
+                self.envelope_speed = value;
+                self.volume.reset_timer(self.envelope_speed);
+                self.modulation.reset_timer(self.envelope_speed);
+            },
+            _ => ()
+        }
+    }
+
+    fn set_mod_counter(&mut self, value: i32) {
+        self.mod_counter = match value {
+            64..=i32::MAX => value - 128,
+            i32::MIN..=-65 => value + 128,
+            _ => value
+        };
+    }
+
+    // the modulator's pitch adjustment, including the hardware's odd rounding
+    //
+    // https://wiki.nesdev.com/w/index.php/FDS_audio#Frequency_calculation
+    fn update_pitch_bend(&mut self) {
+        let mut bend = self.mod_counter * self.modulation.gain as i32;
+        let remainder = bend & 0x0f;
+        bend >>= 4;
+        if remainder > 0 && bend & 0x80 == 0 {
+            bend += if self.mod_counter < 0 { -1 } else { 2 };
+        }
+
+        if bend >= 192 {
+            bend -= 256;
+        } else if bend < -64 {
+            bend += 256;
+        }
+
+        bend *= self.wave_frequency as i32;
+        let remainder = bend & 0x3f;
+        bend >>= 6;
+        if remainder >= 32 {
+            bend += 1;
+        }
+
+        self.pitch_bend = bend;
+    }
+
+    // called once per cpu cycle
+    pub fn clock(&mut self) {
+        if !self.wave_halted && !self.envelopes_disabled {
+            self.volume.clock(self.envelope_speed);
+            if self.modulation.clock(self.envelope_speed) {
+                self.update_pitch_bend();
+            }
+        }
+
+        if !self.mod_halted && self.mod_frequency > 0 {
+            self.mod_accumulator += self.mod_frequency as u32;
+            if self.mod_accumulator > 0xffff {
+                self.mod_accumulator -= 0x10000;
+
+                let step = self.mod_table[self.mod_position] as usize;
+                if step == 4 {
+                    self.mod_counter = 0;
+                } else {
+                    self.set_mod_counter(self.mod_counter + MOD_ADJUSTMENTS[step]);
+                }
+                self.mod_position = (self.mod_position + 1) & 0x3f;
+
+                self.update_pitch_bend();
+            }
+        }
+
+        if self.wave_halted {
+            self.update_output();
+            return;
+        }
+
+        // the output holds its level while the wave table is being written
+        if !self.wave_write_enabled {
+            self.update_output();
+
+            let frequency = self.wave_frequency as i32 + self.pitch_bend;
+            if frequency > 0 {
+                self.wave_accumulator += frequency as u32;
+                if self.wave_accumulator > 0xffff {
+                    self.wave_accumulator -= 0x10000;
+                    self.wave_position = (self.wave_position + 1) & 0x3f;
+                }
+            }
+        }
+    }
+
+    fn update_output(&mut self) {
+        let level = self.volume.gain.min(32) as u32 * MASTER_VOLUMES[self.master_volume as usize];
+        self.output = (self.wave_table[self.wave_position] as u32 * level / 1152) as u8;
+    }
+
+    pub fn output(&self) -> f32 {
+        self.output as f32 / 63.0 * 0.5
+    }
+}
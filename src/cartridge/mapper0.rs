@@ -1,17 +1,23 @@
 use crate::cartridge::Cartridge;
 use crate::cartridge::Mapper;
 use crate::cartridge::Mirror;
+use crate::cartridge::BankedMemory;
 
 pub struct NROM {
     cart: Cartridge,
-    chr_ram: [u8; 0x2000]
+    // NROM-128 only has 16 KiB of prg rom, which ends up mirrored into $C000 by the banked memory
+    prg: BankedMemory,
+    prg_ram: BankedMemory, // not on the original boards, but some dumps and homebrew expect it
+    chr: BankedMemory
 }
 
 impl NROM {
     pub fn new(cart: Cartridge) -> NROM {
         NROM {
-            cart: cart,
-            chr_ram: [0; 0x2000]
+            prg: cart.prg_rom(),
+            prg_ram: cart.prg_ram(),
+            chr: cart.chr_memory(),
+            cart: cart
         }
     }
 }
@@ -19,19 +25,9 @@ impl NROM {
 impl Mapper for NROM {
     fn read(&self, address: usize) -> u8 {
         match address {
-            0x0000..=0x1fff => {
-                if self.cart.header.chr_rom_size > 0 {
-                    self.cart.chr[0][address]
-                } else {
-                    self.chr_ram[address]
-                }
-            },
-            0x8000..=0xbfff => {
-                self.cart.prg[0][address % 0x4000]
-            },
-            0xc000..=0xffff => {
-                self.cart.prg[self.cart.header.prg_rom_size - 1][address % 0x4000]
-            },
+            0x0000..=0x1fff => self.chr.read(address),
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg.read(address),
             _ => {
                 println!("Address out of range! 0x{:X}", address);
                 0
@@ -41,11 +37,8 @@ impl Mapper for NROM {
 
     fn write(&mut self, address: usize, value: u8) {
         match address {
-            0x0000..=0x1fff => {
-                if self.cart.header.chr_rom_size == 0 {
-                    self.chr_ram[address] = value;
-                }
-            },
+            0x0000..=0x1fff => self.chr.write(address, value),
+            0x6000..=0x7fff => self.prg_ram.write(address, value),
             0x8000..=0xffff => {},
             _ => println!("Address out of range! 0x{:X}", address)
         }
@@ -55,7 +48,31 @@ impl Mapper for NROM {
         self.cart.header.mirror
     }
 
-    fn load_battery(&mut self) {}
-    fn save_battery(&self) {}
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
+
+    fn load_battery(&mut self) {
+        self.cart.load_prg_ram(&mut self.prg_ram);
+    }
+
+    fn save_battery(&self) {
+        self.cart.save_prg_ram(&self.prg_ram);
+    }
+
     fn step(&mut self) {}
 }
@@ -1,10 +1,7 @@
 use crate::cartridge::Cartridge;
 use crate::cartridge::Mapper;
 use crate::cartridge::Mirror;
-
-use std::fs::File;
-use std::io::{Read, Write};
-use std::path::Path;
+use crate::cartridge::BankedMemory;
 
 pub struct MMC1 {
     cart: Cartridge,
@@ -12,36 +9,39 @@ pub struct MMC1 {
     shift_register: u8,
     control: u8,
 
-    prg_ram_bank: [u8; 0x2000],
+    prg_ram: BankedMemory,
     prg_ram_enabled: bool,
+    prg: BankedMemory,
     prg_mode: u8,
     prg_bank_select: u8,
 
-    chr_ram_bank: [u8; 0x2000],
+    chr: BankedMemory,
     chr_low_bank: u8,
     chr_high_bank: u8,
-    chr_mode: bool,
+    chr_mode: bool, // false => one 8 KiB bank, true => two 4 KiB banks
 }
 
 impl MMC1 {
     pub fn new(cart: Cartridge) -> MMC1 {
         let mut mmc1 = MMC1 {
-            cart: cart,
             step: 0,
             shift_register: 0,
             control: 0,
 
-            prg_ram_bank: [0; 0x2000],
+            prg_ram: cart.prg_ram(),
             prg_ram_enabled: false,
+            prg: cart.prg_rom(),
             prg_mode: 3,
             prg_bank_select: 0,
 
-            chr_ram_bank: [0; 0x2000],
+            chr: cart.chr_memory(),
             chr_low_bank: 0,
             chr_high_bank: 0,
-            chr_mode: false
+            chr_mode: false,
+
+            cart: cart
         };
-        mmc1.load_battery();
+        mmc1.update_banks();
 
         mmc1
     }
@@ -60,47 +60,54 @@ impl MMC1 {
         self.prg_mode = (value >> 2) & 3;
         self.chr_mode = value & 0x10 != 0;
     }
+
+    // remap prg and chr after any of the registers change
+    fn update_banks(&mut self) {
+        let bank = self.prg_bank_select as usize;
+        match self.prg_mode {
+            // switch 32 KiB at $8000, ignoring the low bit of the bank number
+            0 | 1 => self.prg.map(0x8000, 0x8000, bank >> 1),
+            // fix the first bank at $8000 and switch 16 KiB at $C000
+            2 => {
+                self.prg.map(0x8000, 0x4000, 0);
+                self.prg.map(0xc000, 0x4000, bank);
+            },
+            // fix the last bank at $C000 and switch 16 KiB at $8000
+            3 => {
+                let last_bank = self.prg.last_bank(0x4000);
+                self.prg.map(0x8000, 0x4000, bank);
+                self.prg.map(0xc000, 0x4000, last_bank);
+            },
+            _ => panic!("Bad prg mode!")
+        }
+
+        if self.chr_mode {
+            self.chr.map(0x0000, 0x1000, self.chr_low_bank as usize);
+            self.chr.map(0x1000, 0x1000, self.chr_high_bank as usize);
+        } else {
+            // 8 KiB mode ignores the low bit of the bank number
+            self.chr.map(0x0000, 0x2000, (self.chr_low_bank >> 1) as usize);
+        }
+
+        // https://wiki.nesdev.com/w/index.php/MMC1#SNROM.2C_SOROM.2C_SUROM_and_SXROM
+        //
+        // boards with more than 8 KiB of prg ram select the bank with the chr register,
+        // SOROM with bit 3 and SXROM with bits 2-3
+        let ram_bank = match self.prg_ram.bank_count(0x2000) {
+            1 => 0,
+            2 => (self.chr_low_bank >> 3) & 1,
+            _ => (self.chr_low_bank >> 2) & 3
+        };
+        self.prg_ram.map(0x6000, 0x2000, ram_bank as usize);
+    }
 }
 
 impl Mapper for MMC1 {
     fn read(&self, address: usize) -> u8 {
         match address {
-            0x0000..=0x1fff => {
-                if self.cart.header.chr_rom_size == 0 {
-                    self.chr_ram_bank[address]
-                } else {
-                    if self.chr_mode {
-                        let bank = match address {
-                            0x0000..=0x0fff => self.chr_low_bank,
-                            0x1000..=0x1fff => self.chr_high_bank,
-                            _ => panic!("Address out of range! 0x{:X}", address)
-                        };
-
-                        let chunk_half = if bank % 2 == 0 { 0 } else { 0x1000 };
-
-                        self.cart.chr[(bank / 2) as usize][chunk_half + (address % 0x1000)]
-                    } else {
-                        self.cart.chr[self.chr_low_bank as usize][address]
-                    }
-                }
-            }
-            0x6000..=0x7fff => self.prg_ram_bank[address % 0x2000],
-            0x8000..=0xbfff => {
-                match self.prg_mode {
-                    0 | 1 => self.cart.prg[(self.prg_bank_select & 0xfe) as usize][address % 0x4000],
-                    2 => self.cart.prg[0][address % 0x4000],
-                    3 => self.cart.prg[self.prg_bank_select as usize][address % 0x4000],
-                    _ => panic!("Bad prg mode!")
-                }
-            },
-            0xc000..=0xffff => {
-                match self.prg_mode {
-                    0 | 1 => self.cart.prg[((self.prg_bank_select & 0xfe) + 1) as usize][address % 0x4000],
-                    2 => self.cart.prg[self.prg_bank_select as usize][address % 0x4000],
-                    3 => self.cart.prg[self.cart.header.prg_rom_size - 1][address % 0x4000],
-                    _ => panic!("Bad prg mode!")
-                }
-            },
+            0x0000..=0x1fff => self.chr.read(address),
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg.read(address),
             _ => {
                 println!("Address out of range! 0x{:X}", address);
                 0
@@ -110,18 +117,15 @@ impl Mapper for MMC1 {
 
     fn write(&mut self, address: usize, value: u8) {
         match address {
-            0x0000..=0x1fff => {
-                if self.cart.header.chr_rom_size == 0 {
-                    self.chr_ram_bank[address] = value;
-                }
-            },
-            0x6000..=0x7fff => self.prg_ram_bank[address % 0x2000] = value,
+            0x0000..=0x1fff => self.chr.write(address, value),
+            0x6000..=0x7fff => self.prg_ram.write(address, value),
             0x8000..=0xffff => {
                 // write serial port
                 if value & 0x80 != 0 {
                     self.shift_register = 0;
                     self.step = 0;
                     self.write_control_register(self.control | 0x0c);
+                    self.update_banks();
                 } else {
                     self.shift_register >>= 1;
                     self.shift_register |= (value << 7) & 0x80;
@@ -133,19 +137,11 @@ impl Mapper for MMC1 {
                             0x8000..=0x9fff => self.write_control_register(self.shift_register),
                             0xa000..=0xbfff => {
                                 // write chr low bank
-                                if self.chr_mode {
-                                    self.chr_low_bank = self.shift_register;
-                                } else {
-                                    let v = self.shift_register & 0xfe;
-                                    self.chr_low_bank = v;
-                                    self.chr_high_bank = v + 1;
-                                }
+                                self.chr_low_bank = self.shift_register;
                             },
                             0xc000..=0xdfff => {
                                 // write chr high bank
-                                if self.chr_mode {
-                                    self.chr_high_bank = self.shift_register;
-                                }
+                                self.chr_high_bank = self.shift_register;
                             },
                             0xe000..=0xffff => {
                                 // write prg bank
@@ -155,6 +151,7 @@ impl Mapper for MMC1 {
                             _ => ()
                         }
 
+                        self.update_banks();
                         self.step = 0;
                         self.shift_register = 0;
                     } else {
@@ -170,37 +167,30 @@ impl Mapper for MMC1 {
         self.cart.header.mirror
     }
 
-    fn load_battery(&mut self) {
-        if self.cart.header.battery_backed_ram {
-            let path = Path::new(&self.cart.header.file_path).parent().unwrap();
-            let mut save = path.join(Path::new(&self.cart.header.file_path).file_stem().unwrap());
-            save.set_extension("sav");
-
-            if Path::new(&save).exists() {
-                let mut file = File::open(save.clone()).expect("Failed to open .sav file");
-                let mut battery_ram_buffer = vec![];
-
-                file.read_to_end(&mut battery_ram_buffer).expect("Failed to read .sav file");
-
-                // copy vector data into array
-                self.prg_ram_bank.copy_from_slice(&battery_ram_buffer[..]);
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
 
-                println!("Loaded battery save from: {:?}", save);
-            }
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
         }
     }
 
-    fn save_battery(&self) {
-        if self.cart.header.battery_backed_ram {
-            let path = Path::new(&self.cart.header.file_path).parent().unwrap();
-            let mut save = path.join(Path::new(&self.cart.header.file_path).file_stem().unwrap());
-            save.set_extension("sav");
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
 
-            let mut file = File::create(&save).expect("Failed to create .sav file");
-            file.write_all(&self.prg_ram_bank).expect("Failed to write to .sav file");
+    fn load_battery(&mut self) {
+        self.cart.load_prg_ram(&mut self.prg_ram);
+    }
 
-            println!("Wrote battery save to: {:?}", save);
-        }
+    fn save_battery(&self) {
+        self.cart.save_prg_ram(&self.prg_ram);
     }
 
     fn step(&mut self) {}
@@ -0,0 +1,251 @@
+use crate::cartridge::Cartridge;
+use crate::cartridge::Mapper;
+use crate::cartridge::Mirror;
+use crate::cartridge::BankedMemory;
+use crate::cartridge::eeprom::{Eeprom, EepromKind};
+
+// Bandai FCG-1/2 and LZ93D50, mappers 16, 153, 157 and 159
+//
+// https://wiki.nesdev.com/w/index.php/Bandai_FCG_board
+//
+// the FCG chips decode their registers at $6000-$7FFF, the later LZ93D50 at $8000-$FFFF along with
+// a serial EEPROM for saves. iNES 1.0 mapper 16 could be either, so both ranges are decoded.
+//
+// 153: LZ93D50 with 8 KiB of battery backed prg ram, bit 0 of the chr registers selects the 256 KiB
+//      half of prg rom
+// 157: LZ93D50 with a 24C02 on the Datach barcode reader (the barcode reader and the cartridges'
+//      own 24C01s aren't emulated)
+// 159: LZ93D50 with a 24C01
+pub struct BandaiFCG {
+    cart: Cartridge,
+    prg: BankedMemory,
+    chr: BankedMemory,
+    prg_ram: Option<BankedMemory>, // only on mapper 153
+    eeprom: Option<Eeprom>,
+
+    fcg_registers: bool, // $6000-$7FFF
+    lz93d50_registers: bool, // $8000-$FFFF
+
+    chr_bank_select: [u8; 8],
+    prg_bank_select: u8,
+    prg_ram_enabled: bool,
+    eeprom_read_enabled: bool,
+    mirror: Mirror,
+
+    // 16-bit counter decremented every cpu cycle, the LZ93D50 reloads it from the latch when
+    // enabled while the FCG has the counter written directly
+    irq_counter: u16,
+    irq_latch: u16,
+    irq_enabled: bool,
+    irq_pending: bool
+}
+
+impl BandaiFCG {
+    pub fn new(cart: Cartridge) -> BandaiFCG {
+        let submapper = cart.header.submapper;
+
+        let (fcg_registers, lz93d50_registers) = match (cart.mapper, submapper) {
+            (16, 4) => (true, false),
+            (16, 5) => (false, true),
+            (16, _) => (true, true),
+            _ => (false, true)
+        };
+
+        let eeprom = match (cart.mapper, submapper) {
+            (16, 4) | (153, _) => None,
+            (159, _) => Some(Eeprom::new(EepromKind::X24C01)),
+            _ => Some(Eeprom::new(EepromKind::X24C02))
+        };
+
+        let prg_ram = if cart.mapper == 153 { Some(cart.prg_ram()) } else { None };
+
+        let mut bandai = BandaiFCG {
+            prg: cart.prg_rom(),
+            chr: cart.chr_memory(),
+            prg_ram: prg_ram,
+            eeprom: eeprom,
+
+            fcg_registers: fcg_registers,
+            lz93d50_registers: lz93d50_registers,
+
+            chr_bank_select: [0; 8],
+            prg_bank_select: 0,
+            prg_ram_enabled: false,
+            eeprom_read_enabled: false,
+            mirror: cart.header.mirror,
+
+            irq_counter: 0,
+            irq_latch: 0,
+            irq_enabled: false,
+            irq_pending: false,
+
+            cart: cart
+        };
+        bandai.update_banks();
+
+        bandai
+    }
+
+    fn update_banks(&mut self) {
+        if self.prg_ram.is_some() {
+            // 153 uses the chr registers to select the 256 KiB prg half, and has chr ram
+            let outer = (self.chr_bank_select.iter().fold(0, |bits, bank| bits | bank) & 1) as usize * 16;
+            self.prg.map(0x8000, 0x4000, outer | (self.prg_bank_select & 0x0f) as usize);
+            self.prg.map(0xc000, 0x4000, outer | 0x0f);
+        } else {
+            let last = self.prg.last_bank(0x4000);
+            self.prg.map(0x8000, 0x4000, self.prg_bank_select as usize);
+            self.prg.map(0xc000, 0x4000, last);
+
+            for i in 0..8 {
+                self.chr.map(i * 0x400, 0x400, self.chr_bank_select[i] as usize);
+            }
+        }
+    }
+
+    fn write_register(&mut self, register: usize, value: u8, lz93d50: bool) {
+        match register {
+            0x0..=0x7 => self.chr_bank_select[register] = value,
+            0x8 => self.prg_bank_select = value & 0x0f,
+            0x9 => {
+                self.mirror = match value & 3 {
+                    0 => Mirror::Vertical,
+                    1 => Mirror::Horizontal,
+                    2 => Mirror::Single0,
+                    _ => Mirror::Single1
+                };
+            },
+            // ---- ---E, irq enable. any write acknowledges the irq
+            0xa => {
+                self.irq_enabled = value & 1 != 0;
+                self.irq_pending = false;
+                if lz93d50 {
+                    self.irq_counter = self.irq_latch;
+                }
+            },
+            0xb => {
+                if lz93d50 {
+                    self.irq_latch = (self.irq_latch & 0xff00) | value as u16;
+                } else {
+                    self.irq_counter = (self.irq_counter & 0xff00) | value as u16;
+                }
+            },
+            0xc => {
+                if lz93d50 {
+                    self.irq_latch = (self.irq_latch & 0x00ff) | ((value as u16) << 8);
+                } else {
+                    self.irq_counter = (self.irq_counter & 0x00ff) | ((value as u16) << 8);
+                }
+            },
+            // RDC- ----, eeprom read enable, SDA and SCL. the prg ram enable on 153
+            0xd => {
+                self.prg_ram_enabled = value & 0x20 != 0;
+                self.eeprom_read_enabled = value & 0x80 != 0;
+                if let Some(eeprom) = &mut self.eeprom {
+                    eeprom.write(value & 0x20 != 0, value & 0x40 != 0);
+                }
+            },
+            _ => ()
+        }
+
+        self.update_banks();
+    }
+}
+
+impl Mapper for BandaiFCG {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr.read(address),
+            0x6000..=0x7fff => match (&self.prg_ram, &self.eeprom) {
+                (Some(ram), _) if self.prg_ram_enabled => ram.read(address),
+                // ---D ----, the eeprom's data output
+                (None, Some(eeprom)) if self.eeprom_read_enabled => (eeprom.output() as u8) << 4,
+                _ => 0
+            },
+            0x8000..=0xffff => self.prg.read(address),
+            _ => {
+                println!("Address out of range! 0x{:X}", address);
+                0
+            }
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.chr.write(address, value),
+            0x6000..=0x7fff => {
+                if let Some(ram) = &mut self.prg_ram {
+                    if self.prg_ram_enabled {
+                        ram.write(address, value);
+                    }
+                } else if self.fcg_registers {
+                    self.write_register(address & 0x0f, value, false);
+                }
+            },
+            0x8000..=0xffff => {
+                if self.lz93d50_registers {
+                    self.write_register(address & 0x0f, value, true);
+                }
+            },
+            _ => println!("Address out of range! 0x{:X}", address)
+        }
+    }
+
+    fn get_mirror(&self) -> Mirror {
+        self.mirror
+    }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    // the eeprom is always saved, it's non-volatile whether or not the header says so
+    fn load_battery(&mut self) {
+        if let Some(ram) = &mut self.prg_ram {
+            self.cart.load_prg_ram(ram);
+            return;
+        }
+
+        if let (Some(eeprom), Some((save, data))) = (&mut self.eeprom, self.cart.read_battery()) {
+            if data.len() == eeprom.data.len() {
+                eeprom.data.copy_from_slice(&data);
+                println!("Loaded battery save from: {:?}", save);
+            }
+        }
+    }
+
+    fn save_battery(&self) {
+        match (&self.prg_ram, &self.eeprom) {
+            (Some(ram), _) => self.cart.save_prg_ram(ram),
+            (None, Some(eeprom)) => self.cart.write_battery(&eeprom.data),
+            _ => ()
+        }
+    }
+
+    fn step(&mut self) {
+        if self.irq_enabled {
+            if self.irq_counter == 0 {
+                self.irq_pending = true;
+            }
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+        }
+    }
+}
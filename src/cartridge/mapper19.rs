@@ -0,0 +1,258 @@
+use crate::cartridge::Cartridge;
+use crate::cartridge::Mapper;
+use crate::cartridge::Mirror;
+use crate::cartridge::Fetch;
+use crate::cartridge::BankedMemory;
+use crate::cartridge::namco163_audio::Namco163Audio;
+
+// Namco 129 and 163, mapper 19
+//
+// https://wiki.nesdev.com/w/index.php/INES_Mapper_019
+//
+// every 1 KiB of ppu memory from $0000 to $2FFF is banked, both pattern tables and nametables can
+// be pointed at either chr rom or the console's 2 KiB of nametable ram (ciram). since patterns can
+// come from ciram, the mapper keeps its own copy of it and handles every nametable access itself
+pub struct Namco163 {
+    cart: Cartridge,
+    prg: BankedMemory,
+    prg_ram: BankedMemory,
+    chr: BankedMemory, // $0000-$2FFF
+    ciram: [u8; 0x800],
+    ciram_pages: [Option<usize>; 12], // pages of $0000-$2FFF mapped to ciram instead of chr rom
+
+    chr_bank_select: [u8; 12],
+    chr_ram_disabled: [bool; 2], // per pattern table, banks $E0-$FF select ciram unless disabled
+    prg_ram_protect: u8,
+
+    // 15-bit counter incremented every cpu cycle while enabled, the irq fires at $7FFF
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_pending: bool,
+
+    audio: Namco163Audio,
+    audio_disabled: bool
+}
+
+impl Namco163 {
+    pub fn new(cart: Cartridge) -> Namco163 {
+        let mut prg = cart.prg_rom();
+        let last = prg.last_bank(0x2000);
+        prg.map(0xe000, 0x2000, last);
+
+        let chr = if cart.chr.is_empty() {
+            BankedMemory::ram(0x2000, 0, 0x3000)
+        } else {
+            BankedMemory::rom(cart.chr.clone(), 0, 0x3000)
+        };
+
+        let mut namco = Namco163 {
+            prg: prg,
+            prg_ram: cart.prg_ram(),
+            chr: chr,
+            ciram: [0; 0x800],
+            ciram_pages: [None; 12],
+
+            chr_bank_select: [0; 12],
+            chr_ram_disabled: [false; 2],
+            prg_ram_protect: 0,
+
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_pending: false,
+
+            audio: Namco163Audio::new(),
+            audio_disabled: false,
+
+            cart: cart
+        };
+        namco.update_chr();
+
+        namco
+    }
+
+    fn update_chr(&mut self) {
+        for page in 0..12 {
+            let bank = self.chr_bank_select[page];
+
+            // nametables always use ciram for banks $E0-$FF, pattern tables only if enabled
+            let use_ciram = bank >= 0xe0 && (page >= 8 || !self.chr_ram_disabled[page / 4]);
+            if use_ciram {
+                self.ciram_pages[page] = Some((bank & 1) as usize);
+            } else {
+                self.ciram_pages[page] = None;
+                self.chr.map(page * 0x400, 0x400, bank as usize);
+            }
+        }
+    }
+
+    // $0000-$2FFF, with $3000-$3EFF mirroring the nametables
+    fn ppu_address(address: usize) -> usize {
+        if address >= 0x3000 { address - 0x1000 } else { address }
+    }
+
+    fn read_chr(&self, address: usize) -> u8 {
+        match self.ciram_pages[address / 0x400] {
+            Some(page) => self.ciram[page * 0x400 + (address & 0x3ff)],
+            None => self.chr.read(address)
+        }
+    }
+
+    fn write_chr(&mut self, address: usize, value: u8) {
+        match self.ciram_pages[address / 0x400] {
+            Some(page) => self.ciram[page * 0x400 + (address & 0x3ff)] = value,
+            None => self.chr.write(address, value)
+        }
+    }
+
+    // writes to prg ram need $40 in the high nibble of $F800, each of the low 4 bits then
+    // protects one 2 KiB chunk
+    fn prg_ram_writable(&self, address: usize) -> bool {
+        self.prg_ram_protect & 0xf0 == 0x40 && self.prg_ram_protect & (1 << ((address - 0x6000) / 0x800)) == 0
+    }
+}
+
+impl Mapper for Namco163 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.read_chr(address),
+            0x4800..=0x4fff => self.audio.read_data(),
+            0x5000..=0x57ff => self.irq_counter as u8,
+            0x5800..=0x5fff => ((self.irq_counter >> 8) as u8) | (self.irq_enabled as u8) << 7,
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg.read(address),
+            _ => 0
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.write_chr(address, value),
+            0x4800..=0x4fff => self.audio.write_data(value),
+            // any write to the counter acknowledges the irq
+            0x5000..=0x57ff => {
+                self.irq_counter = (self.irq_counter & 0x7f00) | value as u16;
+                self.irq_pending = false;
+            },
+            0x5800..=0x5fff => {
+                self.irq_counter = (self.irq_counter & 0x00ff) | (((value & 0x7f) as u16) << 8);
+                self.irq_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            },
+            0x6000..=0x7fff => {
+                if self.prg_ram_writable(address) {
+                    self.prg_ram.write(address, value);
+                }
+            },
+            // eight 1 KiB pattern banks at $8000, $8800, ... and four nametable banks at $C000
+            0x8000..=0xdfff => {
+                self.chr_bank_select[(address - 0x8000) / 0x800] = value;
+                self.update_chr();
+            },
+            // -SBB BBBB, sound disable and the prg bank at $8000
+            0xe000..=0xe7ff => {
+                self.prg.map(0x8000, 0x2000, (value & 0x3f) as usize);
+                self.audio_disabled = value & 0x40 != 0;
+            },
+            // HLBB BBBB, ciram disable for the high/low pattern table and the prg bank at $A000
+            0xe800..=0xefff => {
+                self.prg.map(0xa000, 0x2000, (value & 0x3f) as usize);
+                self.chr_ram_disabled = [value & 0x40 != 0, value & 0x80 != 0];
+                self.update_chr();
+            },
+            0xf000..=0xf7ff => self.prg.map(0xc000, 0x2000, (value & 0x3f) as usize),
+            // the audio address port doubles as the prg ram write protection
+            0xf800..=0xffff => {
+                self.audio.write_address(value);
+                self.prg_ram_protect = value;
+            },
+            _ => println!("Address out of range! 0x{:X}", address)
+        }
+    }
+
+    // every nametable access goes through ppu_fetch and ppu_write
+    fn get_mirror(&self) -> Mirror {
+        self.cart.header.mirror
+    }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() && self.ciram_pages[address / 0x400].is_none() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    // pattern fetches from chr rom go through the ppu's normal path, which logs them for the cdl
+    fn ppu_fetch(&mut self, address: usize, _fetch: Fetch) -> Option<u8> {
+        let address = Namco163::ppu_address(address);
+        match address {
+            0x0000..=0x1fff if self.ciram_pages[address / 0x400].is_none() => None,
+            _ => Some(self.read_chr(address))
+        }
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) -> bool {
+        // chr rom mapped as a nametable stays read only
+        self.write_chr(Namco163::ppu_address(address), value);
+        true
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.audio_disabled {
+            0.0
+        } else {
+            self.audio.output()
+        }
+    }
+
+    // prg ram followed by the 128 bytes of internal ram
+    fn load_battery(&mut self) {
+        if !self.cart.header.battery_backed_ram {
+            return;
+        }
+
+        let ram_len = self.prg_ram.data().len();
+        if let Some((save, data)) = self.cart.read_battery() {
+            if data.len() == ram_len + 0x80 {
+                self.prg_ram.data_mut().copy_from_slice(&data[..ram_len]);
+                self.audio.ram.copy_from_slice(&data[ram_len..]);
+                println!("Loaded battery save from: {:?}", save);
+            }
+        }
+    }
+
+    fn save_battery(&self) {
+        if self.cart.header.battery_backed_ram {
+            let mut data = self.prg_ram.data().to_vec();
+            data.extend_from_slice(&self.audio.ram);
+            self.cart.write_battery(&data);
+        }
+    }
+
+    fn step(&mut self) {
+        if self.irq_enabled && self.irq_counter < 0x7fff {
+            self.irq_counter += 1;
+            if self.irq_counter == 0x7fff {
+                self.irq_pending = true;
+            }
+        }
+
+        if !self.audio_disabled {
+            self.audio.clock();
+        }
+    }
+}
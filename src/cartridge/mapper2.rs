@@ -1,19 +1,28 @@
 use crate::cartridge::Cartridge;
 use crate::cartridge::Mapper;
 use crate::cartridge::Mirror;
+use crate::cartridge::BankedMemory;
 
 pub struct UxROM {
     cart: Cartridge,
-    chr_ram: [u8; 0x2000],
-    bank_select: u8
+    prg: BankedMemory,
+    prg_ram: BankedMemory, // not on the original boards, but some dumps and homebrew expect it
+    chr: BankedMemory
 }
 
 impl UxROM {
     pub fn new(cart: Cartridge) -> UxROM {
+        let mut prg = cart.prg_rom();
+
+        // the last bank is fixed at $C000
+        let last_bank = prg.last_bank(0x4000);
+        prg.map(0xc000, 0x4000, last_bank);
+
         UxROM {
-            cart: cart,
-            chr_ram: [0; 0x2000],
-            bank_select: 0
+            prg: prg,
+            prg_ram: cart.prg_ram(),
+            chr: cart.chr_memory(),
+            cart: cart
         }
     }
 }
@@ -21,15 +30,9 @@ impl UxROM {
 impl Mapper for UxROM {
    fn read(&self, address: usize) -> u8 {
         match address {
-            0x0000..=0x1fff => {
-                if self.cart.header.chr_rom_size > 0 {
-                    self.cart.chr[0][address]
-                } else {
-                    self.chr_ram[address]
-                }
-            },
-            0x8000..=0xbfff => self.cart.prg[self.bank_select as usize][address % 0x4000],
-            0xc000..=0xffff => self.cart.prg[self.cart.header.prg_rom_size - 1][address % 0x4000],
+            0x0000..=0x1fff => self.chr.read(address),
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg.read(address),
             _ => {
                 println!("Address out of range! 0x{:X}", address);
                 0
@@ -39,12 +42,9 @@ impl Mapper for UxROM {
 
     fn write(&mut self, address: usize, value: u8) {
         match address {
-            0x0000..=0x1fff => {
-                if self.cart.header.chr_rom_size == 0 {
-                    self.chr_ram[address] = value;
-                }
-            }
-            0x8000..=0xffff => self.bank_select = value,
+            0x0000..=0x1fff => self.chr.write(address, value),
+            0x6000..=0x7fff => self.prg_ram.write(address, value),
+            0x8000..=0xffff => self.prg.map(0x8000, 0x4000, value as usize),
             _ => println!("Address out of range! 0x{:X}", address)
         }
     }
@@ -53,7 +53,31 @@ impl Mapper for UxROM {
         self.cart.header.mirror
     }
 
-    fn load_battery(&mut self) {}
-    fn save_battery(&self) {}
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
+
+    fn load_battery(&mut self) {
+        self.cart.load_prg_ram(&mut self.prg_ram);
+    }
+
+    fn save_battery(&self) {
+        self.cart.save_prg_ram(&self.prg_ram);
+    }
+
     fn step(&mut self) {}
 }
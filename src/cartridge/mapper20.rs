@@ -0,0 +1,571 @@
+use crate::cartridge::Cartridge;
+use crate::cartridge::Mapper;
+use crate::cartridge::Mirror;
+use crate::cartridge::BankedMemory;
+use crate::cartridge::fds_audio::FdsAudio;
+use crate::cartridge::FDS_SIDE_SIZE;
+use crate::patch::{apply_ips, create_ips};
+
+use std::fs;
+use std::cell::Cell;
+use std::path::Path;
+
+// Famicom Disk System, mapper 20
+//
+// https://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System
+//
+// the RAM adapter plugs into the cartridge slot and gives the console 32 KiB of prg ram at
+// $6000-$DFFF, 8 KiB of chr ram and the 8 KiB BIOS at $E000-$FFFF. games are loaded from disk by
+// the BIOS through the adapter's registers at $4020-$4033, which also has a timer irq and the
+// wavetable audio channel at $4040-$4092
+//
+// the disk is a serial stream of bytes read or written one at a time as the head passes over it.
+// disk images only store the blocks of each side, so the gaps and start marks the drive sees are
+// put back in when loading and stripped again when saving. the original image is never written,
+// changes to it are saved as an IPS patch in the .sav file instead
+pub struct FDS {
+    cart: Cartridge,
+    bios: Vec<u8>,
+    prg_ram: [u8; 0x8000],
+    chr: BankedMemory,
+    mirror: Mirror,
+
+    disk_registers_enabled: bool,
+    sound_registers_enabled: bool,
+
+    // 16-bit counter decremented every cpu cycle, reloaded when it fires
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_repeat: bool,
+    timer_irq: Cell<bool>,
+
+    // each side as the drive sees it, gaps and all
+    sides: Vec<Vec<u8>>,
+    disk: Option<usize>, // inserted side
+    ejected_disk: usize, // reinserted by the eject hotkey
+    next_disk: Option<usize>, // inserted once insert_delay runs out
+    insert_delay: u32,
+    modified: bool,
+
+    // $4025
+    motor_on: bool,
+    transfer_reset: bool,
+    read_mode: bool,
+    crc_control: bool,
+    drive_ready: bool,
+    disk_irq_enabled: bool,
+
+    disk_irq: Cell<bool>,
+    transfer_complete: Cell<bool>,
+    read_data: u8,
+    write_data: u8,
+
+    position: usize, // of the head on the current side
+    delay: u32, // cpu cycles until the head reaches the next byte
+    end_of_head: bool,
+    scanning: bool,
+    gap_ended: bool,
+    previous_crc_control: bool,
+    crc: u16,
+
+    audio: FdsAudio
+}
+
+// the drive transfers about 96.4 kbit/s, a byte every ~150 cpu cycles
+const BYTE_CYCLES: u32 = 150;
+const REWIND_CYCLES: u32 = 50000;
+
+// long enough for the BIOS to notice the disk is gone when switching sides, about half a second
+const INSERT_DELAY: u32 = 900_000;
+
+// gaps on the disk, in bytes
+const LEADING_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+
+impl FDS {
+    pub fn new(cart: Cartridge, bios_path: Option<String>) -> FDS {
+        // disksys.rom next to the disk image unless a path is given
+        let bios_path = bios_path.unwrap_or_else(|| {
+            let path = Path::new(&cart.header.file_path).with_file_name("disksys.rom");
+            path.to_string_lossy().into_owned()
+        });
+
+        let bios = match fs::read(&bios_path) {
+            Ok(b) => b,
+            Err(_) => panic!("Cannot load FDS BIOS! {} (use --fds-bios to give its path)", bios_path)
+        };
+        if bios.len() != 0x2000 {
+            panic!("Incorrect FDS BIOS size! {} bytes", bios.len());
+        }
+
+        let mut fds = FDS {
+            bios: bios,
+            prg_ram: [0; 0x8000],
+            chr: cart.chr_memory(),
+            mirror: Mirror::Horizontal,
+
+            disk_registers_enabled: false,
+            sound_registers_enabled: false,
+
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_repeat: false,
+            timer_irq: Cell::new(false),
+
+            sides: Vec::new(),
+            disk: Some(0),
+            ejected_disk: 0,
+            next_disk: None,
+            insert_delay: 0,
+            modified: false,
+
+            motor_on: false,
+            transfer_reset: false,
+            read_mode: true,
+            crc_control: false,
+            drive_ready: false,
+            disk_irq_enabled: false,
+
+            disk_irq: Cell::new(false),
+            transfer_complete: Cell::new(false),
+            read_data: 0,
+            write_data: 0,
+
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            previous_crc_control: false,
+            crc: 0,
+
+            audio: FdsAudio::new(),
+
+            cart: cart
+        };
+        let disk = fds.cart.disk.clone();
+        fds.insert_sides(&disk);
+
+        fds
+    }
+
+    fn insert_sides(&mut self, disk: &[u8]) {
+        self.sides = disk.chunks(FDS_SIDE_SIZE).map(add_gaps).collect();
+    }
+
+    fn side_name(side: usize) -> String {
+        format!("disk {} side {}", side / 2 + 1, if side & 1 == 0 { 'A' } else { 'B' })
+    }
+
+    fn write_register(&mut self, address: usize, value: u8) {
+        match address {
+            0x4020 => self.irq_reload = (self.irq_reload & 0xff00) | value as u16,
+            0x4021 => self.irq_reload = (self.irq_reload & 0x00ff) | ((value as u16) << 8),
+            // ---- --ER, irq enable, repeat
+            0x4022 => {
+                self.irq_repeat = value & 1 != 0;
+                self.irq_enabled = value & 2 != 0 && self.disk_registers_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq.set(false);
+                }
+            },
+            // ---- --SD, sound and disk register enable
+            0x4023 => {
+                self.disk_registers_enabled = value & 1 != 0;
+                self.sound_registers_enabled = value & 2 != 0;
+                if !self.disk_registers_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq.set(false);
+                    self.disk_irq.set(false);
+                }
+            },
+            0x4024 if self.disk_registers_enabled => {
+                self.write_data = value;
+                self.transfer_complete.set(false);
+                self.disk_irq.set(false);
+            },
+            // IS1C MRTD, transfer irq, drive ready, crc, mirroring, read mode, transfer reset, motor
+            0x4025 if self.disk_registers_enabled => {
+                self.motor_on = value & 0x01 != 0;
+                self.transfer_reset = value & 0x02 != 0;
+                self.read_mode = value & 0x04 != 0;
+                self.mirror = if value & 0x08 != 0 { Mirror::Horizontal } else { Mirror::Vertical };
+                self.crc_control = value & 0x10 != 0;
+                self.drive_ready = value & 0x40 != 0;
+                self.disk_irq_enabled = value & 0x80 != 0;
+                self.disk_irq.set(false);
+            },
+            _ => ()
+        }
+    }
+
+    // ---C --DT, crc error, byte transferred, timer irq
+    fn disk_status(&self) -> u8 {
+        self.timer_irq.get() as u8 | (self.transfer_complete.get() as u8) << 1
+    }
+
+    fn read_register(&self, address: usize) -> u8 {
+        if !self.disk_registers_enabled {
+            return 0;
+        }
+
+        match address {
+            // reading acknowledges both irqs
+            0x4030 => {
+                let status = self.disk_status();
+                self.timer_irq.set(false);
+                self.disk_irq.set(false);
+                self.transfer_complete.set(false);
+                status
+            },
+            0x4031 => {
+                self.transfer_complete.set(false);
+                self.disk_irq.set(false);
+                self.read_data
+            },
+            // ---- -WRI, write protected, not ready, not inserted
+            0x4032 => {
+                let inserted = self.disk.is_some();
+                let mut status = 0x40;
+                if !inserted {
+                    status |= 0x05;
+                }
+                if !inserted || !self.scanning {
+                    status |= 0x02;
+                }
+                status
+            },
+            // the battery is always good
+            0x4033 => 0x80,
+            _ => 0
+        }
+    }
+
+    // the disk crc, also calculated while writing so it can be written after each block
+    fn update_crc(&mut self, value: u8) {
+        for bit in 0..8 {
+            let carry = self.crc & 1 != 0;
+            self.crc >>= 1;
+            if carry {
+                self.crc ^= 0x8408;
+            }
+            if value & (1 << bit) != 0 {
+                self.crc ^= 0x8000;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+
+        if self.irq_counter == 0 {
+            self.timer_irq.set(true);
+            self.irq_counter = self.irq_reload;
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    fn clock_drive(&mut self) {
+        if self.insert_delay > 0 {
+            self.insert_delay -= 1;
+            if self.insert_delay == 0 {
+                self.disk = self.next_disk.take();
+                if let Some(side) = self.disk {
+                    println!("Inserted {}", FDS::side_name(side));
+                }
+            }
+        }
+
+        let side = match self.disk {
+            Some(side) if self.motor_on => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+
+        if self.transfer_reset && !self.scanning {
+            return;
+        }
+
+        // the head moves back to the start of the disk before it starts reading again
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+
+        if self.read_mode {
+            let data = self.sides[side][self.position];
+            let mut irq = self.disk_irq_enabled;
+
+            // the start mark at the end of a gap isn't passed on to the cpu
+            if !self.drive_ready {
+                self.gap_ended = false;
+                self.crc = 0;
+            } else if data != 0 && !self.gap_ended {
+                self.gap_ended = true;
+                irq = false;
+            }
+
+            if self.gap_ended {
+                self.transfer_complete.set(true);
+                self.read_data = data;
+                self.disk_irq.set(irq);
+            }
+        } else {
+            let mut data = 0;
+            if !self.crc_control {
+                self.transfer_complete.set(true);
+                data = self.write_data;
+                self.disk_irq.set(self.disk_irq_enabled);
+            }
+
+            if !self.drive_ready {
+                data = 0;
+                self.crc = 0;
+            }
+
+            if !self.crc_control {
+                self.update_crc(data);
+            } else {
+                if !self.previous_crc_control {
+                    self.update_crc(0);
+                    self.update_crc(0);
+                }
+                data = self.crc as u8;
+                self.crc >>= 8;
+            }
+
+            // the write head trails the read head by two bytes
+            if self.position >= 2 {
+                self.sides[side][self.position - 2] = data;
+                self.modified = true;
+            }
+            self.gap_ended = false;
+        }
+
+        self.previous_crc_control = self.crc_control;
+
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            self.motor_on = false;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+}
+
+impl Mapper for FDS {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr.read(address),
+            0x4030..=0x4033 => self.read_register(address),
+            0x4040..=0x4092 if self.sound_registers_enabled => self.audio.read(address) | 0x40,
+            0x6000..=0xdfff => self.prg_ram[address - 0x6000],
+            0xe000..=0xffff => self.bios[address - 0xe000],
+            _ => 0
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.chr.write(address, value),
+            0x4020..=0x4026 => self.write_register(address, value),
+            0x4040..=0x408a if self.sound_registers_enabled => self.audio.write(address, value),
+            0x6000..=0xdfff => self.prg_ram[address - 0x6000] = value,
+            _ => ()
+        }
+    }
+
+    // reading $4030 or $4031 acknowledges the irqs
+    fn peek(&self, address: usize) -> u8 {
+        match address {
+            0x4030 if self.disk_registers_enabled => self.disk_status(),
+            0x4031 if self.disk_registers_enabled => self.read_data,
+            _ => self.read(address)
+        }
+    }
+
+    fn get_mirror(&self) -> Mirror {
+        self.mirror
+    }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    // games run from prg ram and the BIOS, neither is part of the disk image
+    fn prg_rom_offset(&self, _address: usize) -> Option<usize> {
+        None
+    }
+
+    fn chr_rom_offset(&self, _address: usize) -> Option<usize> {
+        None
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.timer_irq.get() || self.disk_irq.get()
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn switch_disk_side(&mut self) {
+        let current = self.disk.or(self.next_disk).unwrap_or(self.ejected_disk);
+        let next = (current + 1) % self.sides.len();
+
+        self.disk = None;
+        self.next_disk = Some(next);
+        self.insert_delay = INSERT_DELAY;
+        println!("Switching to {}", FDS::side_name(next));
+    }
+
+    fn eject_disk(&mut self) {
+        self.next_disk = None;
+        self.insert_delay = 0;
+
+        match self.disk {
+            Some(side) => {
+                self.ejected_disk = side;
+                self.disk = None;
+                println!("Ejected {}", FDS::side_name(side));
+            },
+            None => {
+                self.disk = Some(self.ejected_disk);
+                println!("Inserted {}", FDS::side_name(self.ejected_disk));
+            }
+        }
+    }
+
+    // the .sav file is an IPS patch of the changes made to the disk image
+    fn load_battery(&mut self) {
+        let (save, patch) = match self.cart.read_battery() {
+            Some(battery) => battery,
+            None => return
+        };
+
+        let mut disk = self.cart.disk.clone();
+        match apply_ips(&mut disk, &patch) {
+            Ok(()) => {
+                disk.resize(self.cart.disk.len(), 0);
+                self.insert_sides(&disk);
+                println!("Loaded disk changes from: {:?}", save);
+            },
+            Err(e) => println!("Cannot load disk changes! {:?} {}", save, e)
+        }
+    }
+
+    fn save_battery(&self) {
+        if !self.modified {
+            return;
+        }
+
+        let disk: Vec<u8> = self.sides.iter().flat_map(|side| strip_gaps(side)).collect();
+        self.cart.write_battery(&create_ips(&self.cart.disk, &disk));
+    }
+
+    fn step(&mut self) {
+        self.clock_timer();
+        self.clock_drive();
+        self.audio.clock();
+    }
+}
+
+// length of the block starting at data[0], file data blocks take their size from the file header
+// block before them
+//
+// https://wiki.nesdev.com/w/index.php/FDS_disk_format
+fn block_length(data: &[u8], file_size: usize) -> Option<usize> {
+    match data.first() {
+        Some(1) => Some(56), // disk info
+        Some(2) => Some(2), // file amount
+        Some(3) => Some(16), // file header
+        Some(4) => Some(1 + file_size), // file data
+        _ => None
+    }
+}
+
+fn file_size(header: &[u8]) -> usize {
+    header[13] as usize | (header[14] as usize) << 8
+}
+
+// a side as the drive sees it, blocks are separated by gaps and start with a $80 start mark,
+// followed by a crc (not checked, so it's left as 0)
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEADING_GAP];
+    let mut size = 0;
+
+    let mut i = 0;
+    while let Some(length) = block_length(&side[i..], size) {
+        if i + length > side.len() {
+            break;
+        }
+
+        if side[i] == 3 {
+            size = file_size(&side[i..]);
+        }
+
+        raw.push(0x80);
+        raw.extend_from_slice(&side[i..i + length]);
+        raw.extend_from_slice(&[0, 0]);
+        raw.extend_from_slice(&[0; BLOCK_GAP]);
+
+        i += length;
+    }
+
+    // room past the last block for the game to write more files
+    raw.resize(raw.len().max(FDS_SIDE_SIZE + LEADING_GAP), 0);
+    raw
+}
+
+// undo add_gaps, picking out the blocks after each start mark
+fn strip_gaps(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(FDS_SIDE_SIZE);
+    let mut size = 0;
+
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] != 0x80 {
+            i += 1;
+            continue;
+        }
+        i += 1;
+
+        let length = match block_length(&raw[i..], size) {
+            Some(length) if i + length <= raw.len() => length,
+            _ => break
+        };
+
+        if raw[i] == 3 {
+            size = file_size(&raw[i..]);
+        }
+
+        side.extend_from_slice(&raw[i..i + length]);
+        i += length + 2; // skip the crc
+    }
+
+    side.resize(FDS_SIDE_SIZE, 0);
+    side
+}
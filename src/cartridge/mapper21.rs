@@ -0,0 +1,207 @@
+use crate::cartridge::Cartridge;
+use crate::cartridge::Mapper;
+use crate::cartridge::Mirror;
+use crate::cartridge::BankedMemory;
+use crate::cartridge::vrc_irq::VrcIrq;
+
+// Konami VRC2 and VRC4, covering mappers 21, 22, 23 and 25
+//
+// https://wiki.nesdev.com/w/index.php/VRC2_and_VRC4
+//
+// every board variant connects different cpu address lines to the chip's two register select
+// pins, e.g. VRC4a uses A1/A2 while VRC4c uses A6/A7, and both are mapper 21. NES 2.0 submappers
+// say exactly which variant a game uses. for iNES 1.0 headers all of the mapper's possible
+// wirings are ORed together, which works since games only ever write to the addresses of their
+// own wiring, with the unused lines held low
+pub struct VRC4 {
+    cart: Cartridge,
+    prg: BankedMemory,
+    chr: BankedMemory,
+    prg_ram: BankedMemory,
+
+    // (A0, A1) pairs, the cpu address bit connected to each register select pin
+    wirings: Vec<(usize, usize)>,
+    vrc2: bool,
+    chr_shift: u8, // VRC2a ignores the low bit of the chr bank numbers
+
+    prg_bank_select: [u8; 2],
+    prg_swap_mode: bool, // false => $8000 swappable, true => $C000 swappable
+    chr_bank_select: [u16; 8],
+    mirror: Mirror,
+
+    irq: VrcIrq
+}
+
+impl VRC4 {
+    pub fn new(cart: Cartridge) -> VRC4 {
+        let (wirings, vrc2) = match (cart.mapper, cart.header.submapper) {
+            (21, 1) => (vec![(1, 2)], false), // VRC4a
+            (21, 2) => (vec![(6, 7)], false), // VRC4c
+            (21, _) => (vec![(1, 2), (6, 7)], false),
+            (22, _) => (vec![(1, 0)], true), // VRC2a
+            (23, 1) => (vec![(0, 1)], false), // VRC4f
+            (23, 2) => (vec![(2, 3)], false), // VRC4e
+            (23, 3) => (vec![(0, 1)], true), // VRC2b
+            (23, _) => (vec![(0, 1), (2, 3)], false),
+            (25, 1) => (vec![(1, 0)], false), // VRC4b
+            (25, 2) => (vec![(3, 2)], false), // VRC4d
+            (25, 3) => (vec![(1, 0)], true), // VRC2c
+            (25, _) => (vec![(1, 0), (3, 2)], false),
+            _ => panic!("Not a VRC2/VRC4 mapper! {}", cart.mapper)
+        };
+
+        let mut vrc = VRC4 {
+            prg: cart.prg_rom(),
+            chr: cart.chr_memory(),
+            prg_ram: cart.prg_ram(),
+
+            wirings: wirings,
+            vrc2: vrc2,
+            chr_shift: if cart.mapper == 22 { 1 } else { 0 },
+
+            prg_bank_select: [0, 0],
+            prg_swap_mode: false,
+            chr_bank_select: [0; 8],
+            mirror: cart.header.mirror,
+
+            irq: VrcIrq::new(),
+
+            cart: cart
+        };
+        vrc.update_banks();
+
+        vrc
+    }
+
+    // collapse the cpu address into the register number 0-3 selected by the board's wiring
+    fn register(&self, address: usize) -> usize {
+        self.wirings.iter().fold(0, |register, &(a0, a1)| {
+            register | ((address >> a0) & 1) | (((address >> a1) & 1) << 1)
+        })
+    }
+
+    fn update_banks(&mut self) {
+        let second_last = self.prg.last_bank(0x2000).saturating_sub(1);
+        let last = self.prg.last_bank(0x2000);
+
+        let (bank_8000, bank_c000) = if self.prg_swap_mode {
+            (second_last, self.prg_bank_select[0] as usize)
+        } else {
+            (self.prg_bank_select[0] as usize, second_last)
+        };
+
+        self.prg.map(0x8000, 0x2000, bank_8000);
+        self.prg.map(0xa000, 0x2000, self.prg_bank_select[1] as usize);
+        self.prg.map(0xc000, 0x2000, bank_c000);
+        self.prg.map(0xe000, 0x2000, last);
+
+        for i in 0..8 {
+            let bank = (self.chr_bank_select[i] >> self.chr_shift) as usize;
+            self.chr.map(i * 0x400, 0x400, bank);
+        }
+    }
+
+    fn write_chr_select(&mut self, slot: usize, high: bool, value: u8) {
+        let bank = self.chr_bank_select[slot];
+        self.chr_bank_select[slot] = if high {
+            // VRC2 only has 4 high bits, VRC4 has 5
+            let mask = if self.vrc2 { 0x0f } else { 0x1f };
+            (bank & 0x0f) | (((value & mask) as u16) << 4)
+        } else {
+            (bank & 0x1f0) | (value & 0x0f) as u16
+        };
+    }
+}
+
+impl Mapper for VRC4 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr.read(address),
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg.read(address),
+            _ => {
+                println!("Address out of range! 0x{:X}", address);
+                0
+            }
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.chr.write(address, value),
+            0x6000..=0x7fff => self.prg_ram.write(address, value),
+            0x8000..=0xffff => {
+                let register = self.register(address);
+
+                match (address & 0xf000, register) {
+                    (0x8000, _) => self.prg_bank_select[0] = value & 0x1f,
+                    (0x9000, 0) | (0x9000, 1) => {
+                        // VRC2 only has the vertical/horizontal bit
+                        let mask = if self.vrc2 { 1 } else { 3 };
+                        self.mirror = match value & mask {
+                            0 => Mirror::Vertical,
+                            1 => Mirror::Horizontal,
+                            2 => Mirror::Single0,
+                            _ => Mirror::Single1
+                        };
+                    },
+                    (0x9000, _) if !self.vrc2 => self.prg_swap_mode = value & 2 != 0,
+                    (0xa000, _) => self.prg_bank_select[1] = value & 0x1f,
+                    (0xb000..=0xe000, _) => {
+                        // two registers per 1 KiB bank, the low and high nibble of its number
+                        let slot = ((address & 0xf000) - 0xb000) / 0x1000 * 2 + register / 2;
+                        self.write_chr_select(slot, register & 1 != 0, value);
+                    },
+                    (0xf000, 0) => self.irq.write_latch_low(value),
+                    (0xf000, 1) => self.irq.write_latch_high(value),
+                    (0xf000, 2) => self.irq.write_control(value),
+                    (0xf000, _) => self.irq.acknowledge(),
+                    _ => ()
+                }
+
+                self.update_banks();
+            },
+            _ => println!("Address out of range! 0x{:X}", address)
+        }
+    }
+
+    fn get_mirror(&self) -> Mirror {
+        self.mirror
+    }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn load_battery(&mut self) {
+        self.cart.load_prg_ram(&mut self.prg_ram);
+    }
+
+    fn save_battery(&self) {
+        self.cart.save_prg_ram(&self.prg_ram);
+    }
+
+    fn step(&mut self) {
+        if !self.vrc2 {
+            self.irq.clock();
+        }
+    }
+}
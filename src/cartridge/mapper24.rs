@@ -0,0 +1,202 @@
+use crate::cartridge::Cartridge;
+use crate::cartridge::Mapper;
+use crate::cartridge::Mirror;
+use crate::cartridge::BankedMemory;
+use crate::cartridge::vrc_irq::VrcIrq;
+use crate::cartridge::vrc6_audio::Vrc6Audio;
+
+// Konami VRC6, mapper 24 (VRC6a) and mapper 26 (VRC6b)
+//
+// https://wiki.nesdev.com/w/index.php/VRC6
+//
+// the two boards only differ in having the A0 and A1 register select lines swapped
+pub struct VRC6 {
+    cart: Cartridge,
+    prg: BankedMemory,
+    chr: BankedMemory,
+    prg_ram: BankedMemory,
+    prg_ram_enabled: bool,
+
+    swap_lines: bool, // VRC6b
+
+    prg_bank_16k: u8, // $8000-$BFFF
+    prg_bank_8k: u8, // $C000-$DFFF
+    chr_bank_select: [u8; 8],
+    chr_mode: u8,
+    mirror: Mirror,
+
+    irq: VrcIrq,
+    audio: Vrc6Audio
+}
+
+impl VRC6 {
+    pub fn new(cart: Cartridge) -> VRC6 {
+        let mut vrc = VRC6 {
+            prg: cart.prg_rom(),
+            chr: cart.chr_memory(),
+            prg_ram: cart.prg_ram(),
+            prg_ram_enabled: false,
+
+            swap_lines: cart.mapper == 26,
+
+            prg_bank_16k: 0,
+            prg_bank_8k: 0,
+            chr_bank_select: [0; 8],
+            chr_mode: 0,
+            mirror: cart.header.mirror,
+
+            irq: VrcIrq::new(),
+            audio: Vrc6Audio::new(),
+
+            cart: cart
+        };
+        vrc.update_banks();
+
+        vrc
+    }
+
+    fn update_banks(&mut self) {
+        self.prg.map(0x8000, 0x4000, self.prg_bank_16k as usize);
+        self.prg.map(0xc000, 0x2000, self.prg_bank_8k as usize);
+        let last = self.prg.last_bank(0x2000);
+        self.prg.map(0xe000, 0x2000, last);
+
+        // 2 KiB banks ignore the low bit of their register, which is replaced by ppu A10
+        let r = self.chr_bank_select;
+        match self.chr_mode {
+            // eight 1 KiB banks
+            0 => {
+                for (i, &bank) in r.iter().enumerate() {
+                    self.chr.map(i * 0x400, 0x400, bank as usize);
+                }
+            },
+            // four 2 KiB banks
+            1 => {
+                for (i, &bank) in r.iter().take(4).enumerate() {
+                    self.chr.map(i * 0x800, 0x800, (bank >> 1) as usize);
+                }
+            },
+            // four 1 KiB banks followed by two 2 KiB banks
+            _ => {
+                for (i, &bank) in r.iter().take(4).enumerate() {
+                    self.chr.map(i * 0x400, 0x400, bank as usize);
+                }
+                self.chr.map(0x1000, 0x800, (r[4] >> 1) as usize);
+                self.chr.map(0x1800, 0x800, (r[5] >> 1) as usize);
+            }
+        }
+    }
+
+    // W.PN MMDD, ppu banking style
+    //
+    // only the common configurations are supported, DD picks the chr mode and MM the mirroring,
+    // the nametables are never mapped to chr rom
+    fn write_banking_style(&mut self, value: u8) {
+        self.chr_mode = value & 3;
+        self.mirror = match (value >> 2) & 3 {
+            0 => Mirror::Vertical,
+            1 => Mirror::Horizontal,
+            2 => Mirror::Single0,
+            _ => Mirror::Single1
+        };
+        self.prg_ram_enabled = value & 0x80 != 0;
+    }
+}
+
+impl Mapper for VRC6 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr.read(address),
+            0x6000..=0x7fff => {
+                if self.prg_ram_enabled {
+                    self.prg_ram.read(address)
+                } else {
+                    0
+                }
+            },
+            0x8000..=0xffff => self.prg.read(address),
+            _ => {
+                println!("Address out of range! 0x{:X}", address);
+                0
+            }
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.chr.write(address, value),
+            0x6000..=0x7fff => {
+                if self.prg_ram_enabled {
+                    self.prg_ram.write(address, value);
+                }
+            },
+            0x8000..=0xffff => {
+                let register = if self.swap_lines {
+                    ((address & 1) << 1) | ((address >> 1) & 1)
+                } else {
+                    address & 3
+                };
+                let address = (address & 0xf000) | register;
+
+                match address {
+                    0x8000..=0x8003 => self.prg_bank_16k = value & 0x0f,
+                    0x9000..=0xb002 => self.audio.write(address, value),
+                    0xb003 => self.write_banking_style(value),
+                    0xc000..=0xc003 => self.prg_bank_8k = value & 0x1f,
+                    0xd000..=0xd003 => self.chr_bank_select[register] = value,
+                    0xe000..=0xe003 => self.chr_bank_select[4 + register] = value,
+                    0xf000 => self.irq.write_latch(value),
+                    0xf001 => self.irq.write_control(value),
+                    0xf002 => self.irq.acknowledge(),
+                    _ => ()
+                }
+
+                self.update_banks();
+            },
+            _ => println!("Address out of range! 0x{:X}", address)
+        }
+    }
+
+    fn get_mirror(&self) -> Mirror {
+        self.mirror
+    }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn load_battery(&mut self) {
+        self.cart.load_prg_ram(&mut self.prg_ram);
+    }
+
+    fn save_battery(&self) {
+        self.cart.save_prg_ram(&self.prg_ram);
+    }
+
+    fn step(&mut self) {
+        self.irq.clock();
+        self.audio.clock();
+    }
+}
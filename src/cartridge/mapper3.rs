@@ -1,21 +1,26 @@
 use crate::cartridge::Cartridge;
 use crate::cartridge::Mapper;
 use crate::cartridge::Mirror;
+use crate::cartridge::BankedMemory;
 
 pub struct CNROM {
     cart: Cartridge,
+    prg: BankedMemory,
+    prg_ram: BankedMemory, // not on the original boards, but some dumps and homebrew expect it
     // https://wiki.nesdev.com/w/index.php/CNROM#Bank_select_.28.248000-.24FFFF.29
     //
     // "Select 8 KB CHR ROM bank for PPU $0000-$1FFF"
     // only the lower 2 bits of the value is used for this
-    bank_select: u8
+    chr: BankedMemory
 }
 
 impl CNROM {
     pub fn new(cart: Cartridge) -> CNROM {
         CNROM {
-            cart: cart,
-            bank_select: 0,
+            prg: cart.prg_rom(),
+            prg_ram: cart.prg_ram(),
+            chr: cart.chr_memory(),
+            cart: cart
         }
     }
 }
@@ -23,9 +28,9 @@ impl CNROM {
 impl Mapper for CNROM {
     fn read(&self, address: usize) -> u8 {
         match address {
-            0x0000..=0x1fff => self.cart.chr[self.bank_select as usize][address],
-            0x8000..=0xbfff => self.cart.prg[0][address % 0x4000],
-            0xc000..=0xffff => self.cart.prg[self.cart.header.prg_rom_size - 1][address % 0x4000],
+            0x0000..=0x1fff => self.chr.read(address),
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg.read(address),
             _ => {
                 println!("Address out of range! 0x{:X}", address);
                 0
@@ -35,9 +40,10 @@ impl Mapper for CNROM {
 
     fn write(&mut self, address: usize, value: u8) {
         match address {
+            0x6000..=0x7fff => self.prg_ram.write(address, value),
             0x8000..=0xffff => {
                 // write the first 2 bits into the bank select
-                self.bank_select = value & 3;
+                self.chr.map(0x0000, 0x2000, (value & 3) as usize);
             },
             _ => println!("Address out of range! 0x{:X}", address)
         }
@@ -47,7 +53,31 @@ impl Mapper for CNROM {
         self.cart.header.mirror
     }
 
-    fn load_battery(&mut self) {}
-    fn save_battery(&self) {}
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
+
+    fn load_battery(&mut self) {
+        self.cart.load_prg_ram(&mut self.prg_ram);
+    }
+
+    fn save_battery(&self) {
+        self.cart.save_prg_ram(&self.prg_ram);
+    }
+
     fn step(&mut self) {}
 }
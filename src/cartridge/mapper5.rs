@@ -0,0 +1,511 @@
+use crate::cartridge::Cartridge;
+use crate::cartridge::Mapper;
+use crate::cartridge::Mirror;
+use crate::cartridge::Fetch;
+use crate::cartridge::BankedMemory;
+
+use std::cell::Cell;
+
+// Nintendo MMC5, mapper 5
+//
+// https://wiki.nesdev.com/w/index.php/MMC5
+//
+// the MMC5 has no idea where the ppu is on screen, instead it watches the ppu's fetches. the ppu
+// reads the same nametable byte three times in a row at the end of every rendered scanline (two
+// unused fetches at dots 337 and 339, then the first fetch of the next line), which drives the
+// scanline counter. counting the nametable fetches within a line gives the tile column for the
+// vertical split, and the nametable fetch of every tile picks its extended attributes. the frame
+// ends once the ppu stops fetching for 3 cpu cycles, see ppu_idle.
+//
+// the expansion audio isn't implemented
+pub struct MMC5 {
+    cart: Cartridge,
+    prg: BankedMemory,
+    prg_ram: BankedMemory, // mapped to $6000-$7FFF, and optionally over any of $8000-$DFFF
+
+    // chr set A is used for sprites and set B for the background while 8x16 sprites are enabled,
+    // otherwise the last written set is used for everything. each has its own copy of chr, which
+    // write_chr keeps the same for boards with chr ram
+    chr_sprites: BankedMemory,
+    chr_background: BankedMemory,
+    chr_extra: BankedMemory, // 4 KiB banks picked per tile, for extended attributes and the split
+    exram: [u8; 0x400],
+
+    prg_mode: u8,
+    prg_bank_select: [u8; 5], // $5113-$5117
+    prg_slot_ram: [bool; 4], // which 8 KiB slots of $8000-$FFFF are mapped to prg ram
+    prg_ram_protect: [u8; 2], // writes are only allowed with $5102 = 2 and $5103 = 1
+
+    chr_mode: u8,
+    chr_bank_select: [u16; 12], // $5120-$512B
+    chr_upper_bits: u16, // $5130
+    chr_last_set_b: bool,
+    sprites_8x16: bool,
+
+    exram_mode: u8,
+    nametable_select: u8, // 2 bits per quadrant: ciram 0, ciram 1, exram, fill
+    fill_tile: u8,
+    fill_attribute: u8,
+
+    split_control: u8, // ESWW WWWW, enable, right side, tile threshold
+    split_scroll: u8,
+    split_bank: u8,
+
+    // fetch tracking
+    last_nametable_address: usize,
+    nametable_matches: u8,
+    in_frame: bool,
+    scanline: u8,
+    tile: u8, // tile column of the current background fetch, 0-1 are the prefetch for the next line
+    tile_attribute: u8, // exram byte of the current tile, for extended attributes
+    tile_in_split: bool,
+    split_fine_y: usize,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: Cell<bool>, // acknowledged by reading $5204
+
+    multiplicand: u8,
+    multiplier: u8
+}
+
+impl MMC5 {
+    pub fn new(cart: Cartridge) -> MMC5 {
+        let mut mmc5 = MMC5 {
+            prg: cart.prg_rom(),
+            prg_ram: cart.prg_ram_window(0x10000, 0xa000),
+
+            chr_sprites: cart.chr_memory(),
+            chr_background: cart.chr_memory(),
+            chr_extra: cart.chr_memory(),
+            exram: [0; 0x400],
+
+            prg_mode: 3,
+            prg_bank_select: [0, 0, 0, 0, 0xff],
+            prg_slot_ram: [false; 4],
+            prg_ram_protect: [0; 2],
+
+            chr_mode: 0,
+            chr_bank_select: [0; 12],
+            chr_upper_bits: 0,
+            chr_last_set_b: false,
+            sprites_8x16: false,
+
+            exram_mode: 0,
+            nametable_select: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+
+            last_nametable_address: 0,
+            nametable_matches: 0,
+            in_frame: false,
+            scanline: 0,
+            tile: 1,
+            tile_attribute: 0,
+            tile_in_split: false,
+            split_fine_y: 0,
+
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: Cell::new(false),
+
+            multiplicand: 0xff,
+            multiplier: 0xff,
+
+            cart: cart
+        };
+        mmc5.update_banks();
+
+        mmc5
+    }
+
+    fn update_banks(&mut self) {
+        let bank = self.prg_bank_select;
+        self.prg_ram.map(0x6000, 0x2000, bank[0] as usize);
+
+        // $5117 is always rom
+        match self.prg_mode {
+            0 => self.map_prg(0x8000, 0x8000, bank[4] | 0x80),
+            1 => {
+                self.map_prg(0x8000, 0x4000, bank[2]);
+                self.map_prg(0xc000, 0x4000, bank[4] | 0x80);
+            },
+            2 => {
+                self.map_prg(0x8000, 0x4000, bank[2]);
+                self.map_prg(0xc000, 0x2000, bank[3]);
+                self.map_prg(0xe000, 0x2000, bank[4] | 0x80);
+            },
+            _ => {
+                self.map_prg(0x8000, 0x2000, bank[1]);
+                self.map_prg(0xa000, 0x2000, bank[2]);
+                self.map_prg(0xc000, 0x2000, bank[3]);
+                self.map_prg(0xe000, 0x2000, bank[4] | 0x80);
+            }
+        }
+
+        let a = self.chr_bank_select;
+        let b = &a[8..12];
+        match self.chr_mode {
+            0 => {
+                self.chr_sprites.map(0x0000, 0x2000, a[7] as usize);
+                self.chr_background.map(0x0000, 0x2000, b[3] as usize);
+            },
+            1 => {
+                self.chr_sprites.map(0x0000, 0x1000, a[3] as usize);
+                self.chr_sprites.map(0x1000, 0x1000, a[7] as usize);
+                self.chr_background.map(0x0000, 0x1000, b[3] as usize);
+                self.chr_background.map(0x1000, 0x1000, b[3] as usize);
+            },
+            2 => {
+                for i in 0..4 {
+                    self.chr_sprites.map(i * 0x800, 0x800, a[i * 2 + 1] as usize);
+                    self.chr_background.map(i * 0x800, 0x800, b[(i % 2) * 2 + 1] as usize);
+                }
+            },
+            _ => {
+                for i in 0..8 {
+                    self.chr_sprites.map(i * 0x400, 0x400, a[i] as usize);
+                    self.chr_background.map(i * 0x400, 0x400, b[i % 4] as usize);
+                }
+            }
+        }
+    }
+
+    // RBBB BBBB, rom (1) or ram (0) and the bank number in 8 KiB units, the low bits are ignored
+    // for bigger banks
+    fn map_prg(&mut self, address: usize, size: usize, value: u8) {
+        let rom = value & 0x80 != 0;
+        let bank = (value & 0x7f) as usize / (size / 0x2000);
+
+        if rom {
+            self.prg.map(address, size, bank);
+        } else {
+            self.prg_ram.map(address, size, bank);
+        }
+
+        let first_slot = (address - 0x8000) / 0x2000;
+        for slot in first_slot..(first_slot + size / 0x2000) {
+            self.prg_slot_ram[slot] = !rom;
+        }
+    }
+
+    fn write_chr(&mut self, address: usize, value: u8) {
+        if !self.chr_sprites.is_ram() {
+            return;
+        }
+
+        let offset = self.chr_set(Fetch::Cpu).offset(address);
+        for chr in [&mut self.chr_sprites, &mut self.chr_background, &mut self.chr_extra].iter_mut() {
+            chr.data_mut()[offset] = value;
+        }
+    }
+
+    // IF-- ----, irq pending and in frame
+    fn irq_status(&self) -> u8 {
+        (self.irq_pending.get() as u8) << 7 | (self.in_frame as u8) << 6
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [2, 1]
+    }
+
+    fn chr_set(&self, fetch: Fetch) -> &BankedMemory {
+        let set_b = match fetch {
+            Fetch::Sprite if self.sprites_8x16 => false,
+            Fetch::Background if self.sprites_8x16 => true,
+            _ => self.chr_last_set_b
+        };
+
+        if set_b {
+            &self.chr_background
+        } else {
+            &self.chr_sprites
+        }
+    }
+
+    // nametable quadrants mapped to exram or fill mode, None for the ppu's own ciram
+    fn read_nametable(&self, address: usize) -> Option<u8> {
+        let quadrant = (address & 0x0fff) >> 10;
+        let offset = address & 0x03ff;
+
+        match (self.nametable_select >> (quadrant * 2)) & 3 {
+            2 if self.exram_mode < 2 => Some(self.exram[offset]),
+            2 => Some(0),
+            3 if offset >= 0x3c0 => Some(self.fill_attribute * 0x55),
+            3 => Some(self.fill_tile),
+            _ => None
+        }
+    }
+
+    // the third fetch of the same nametable address in a row starts a new scanline
+    fn watch_nametable_fetch(&mut self, address: usize) {
+        if address == self.last_nametable_address {
+            self.nametable_matches += 1;
+        } else {
+            self.last_nametable_address = address;
+            self.nametable_matches = 0;
+        }
+
+        if self.nametable_matches == 2 {
+            if self.in_frame {
+                self.scanline = self.scanline.wrapping_add(1);
+                if self.scanline == self.irq_compare {
+                    self.irq_pending.set(true);
+                }
+            } else {
+                self.in_frame = true;
+                self.scanline = 0;
+            }
+
+            // this fetch is the third tile of the line, the first two were prefetched
+            self.tile = 2;
+        } else {
+            self.tile = (self.tile + 1) % 34;
+        }
+    }
+
+    fn fetch_nametable(&mut self, address: usize) -> Option<u8> {
+        self.watch_nametable_fetch(address);
+
+        let threshold = self.split_control & 0x1f;
+        let tile = self.tile;
+        self.tile_in_split = self.split_control & 0x80 != 0 && self.exram_mode < 2 && tile < 32 && if self.split_control & 0x40 != 0 {
+            tile >= threshold
+        } else {
+            tile < threshold
+        };
+
+        if self.tile_in_split {
+            // the prefetched tiles belong to the next line
+            let line = if !self.in_frame {
+                0
+            } else if tile < 2 {
+                self.scanline as usize + 1
+            } else {
+                self.scanline as usize
+            };
+
+            let y = (self.split_scroll as usize + line) % 240;
+            self.split_fine_y = y % 8;
+            return Some(self.exram[(y / 8) * 32 + tile as usize]);
+        }
+
+        if self.exram_mode == 1 {
+            self.tile_attribute = self.exram[address & 0x03ff];
+        }
+
+        self.read_nametable(address)
+    }
+
+    fn fetch_attribute(&mut self, address: usize) -> Option<u8> {
+        if self.tile_in_split {
+            let tile = self.tile as usize;
+            let coarse_y = (self.split_scroll as usize + self.scanline as usize) % 240 / 8;
+            let byte = self.exram[0x3c0 + (coarse_y / 4) * 8 + tile / 4];
+
+            // the ppu picks the quadrant from its own scroll position, so hand it the one the
+            // split actually needs in every position
+            let shift = ((coarse_y & 2) << 1) | (tile & 2);
+            return Some(((byte >> shift) & 3) * 0x55);
+        }
+
+        if self.exram_mode == 1 {
+            return Some((self.tile_attribute >> 6) * 0x55);
+        }
+
+        self.read_nametable(address)
+    }
+
+    fn fetch_background(&mut self, address: usize) -> u8 {
+        if self.tile_in_split {
+            self.chr_extra.map(0x0000, 0x1000, self.split_bank as usize);
+            return self.chr_extra.read((address & 0x0ff8) | self.split_fine_y);
+        }
+
+        if self.exram_mode == 1 {
+            let bank = (self.tile_attribute & 0x3f) as usize | (self.chr_upper_bits as usize) << 6;
+            self.chr_extra.map(0x0000, 0x1000, bank);
+            return self.chr_extra.read(address & 0x0fff);
+        }
+
+        self.chr_set(Fetch::Background).read(address)
+    }
+}
+
+impl Mapper for MMC5 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr_set(Fetch::Cpu).read(address),
+            0x5204 => {
+                let status = self.irq_status();
+                self.irq_pending.set(false);
+                status
+            },
+            0x5205 => (self.multiplicand as u16 * self.multiplier as u16) as u8,
+            0x5206 => ((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8,
+            0x5c00..=0x5fff if self.exram_mode >= 2 => self.exram[address - 0x5c00],
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => {
+                if self.prg_slot_ram[(address - 0x8000) / 0x2000] {
+                    self.prg_ram.read(address)
+                } else {
+                    self.prg.read(address)
+                }
+            },
+            // audio and unused registers
+            _ => 0
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.write_chr(address, value),
+            0x5100 => {
+                self.prg_mode = value & 3;
+                self.update_banks();
+            },
+            0x5101 => {
+                self.chr_mode = value & 3;
+                self.update_banks();
+            },
+            0x5102 => self.prg_ram_protect[0] = value & 3,
+            0x5103 => self.prg_ram_protect[1] = value & 3,
+            0x5104 => self.exram_mode = value & 3,
+            0x5105 => self.nametable_select = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 3,
+            0x5113..=0x5117 => {
+                self.prg_bank_select[address - 0x5113] = value;
+                self.update_banks();
+            },
+            0x5120..=0x512b => {
+                let i = address - 0x5120;
+                self.chr_bank_select[i] = value as u16 | self.chr_upper_bits << 8;
+                self.chr_last_set_b = i >= 8;
+                self.update_banks();
+            },
+            0x5130 => self.chr_upper_bits = (value & 3) as u16,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            // exram can only be written while rendering in the nametable modes, zero otherwise
+            0x5c00..=0x5fff => match self.exram_mode {
+                0 | 1 => self.exram[address - 0x5c00] = if self.in_frame { value } else { 0 },
+                2 => self.exram[address - 0x5c00] = value,
+                _ => ()
+            },
+            0x6000..=0x7fff if self.prg_ram_writable() => self.prg_ram.write(address, value),
+            0x8000..=0xdfff if self.prg_ram_writable() && self.prg_slot_ram[(address - 0x8000) / 0x2000] => {
+                self.prg_ram.write(address, value);
+            },
+            // audio and unused registers
+            _ => ()
+        }
+    }
+
+    // reading $5204 acknowledges the irq
+    fn peek(&self, address: usize) -> u8 {
+        match address {
+            0x5204 => self.irq_status(),
+            _ => self.read(address)
+        }
+    }
+
+    fn get_mirror(&self) -> Mirror {
+        // quadrants that aren't mapped to ciram are handled through ppu_fetch
+        let select = self.nametable_select as usize;
+        Mirror::Custom([select & 1, (select >> 2) & 1, (select >> 4) & 1, (select >> 6) & 1])
+    }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff if !self.prg_slot_ram[(address - 0x8000) / 0x2000] => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr_sprites.is_ram() => Some(self.chr_set(Fetch::Cpu).offset(address)),
+            _ => None
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_enabled && self.irq_pending.get()
+    }
+
+    fn ppu_fetch(&mut self, address: usize, fetch: Fetch) -> Option<u8> {
+        match (fetch, address) {
+            (Fetch::Nametable, _) => self.fetch_nametable(address),
+            (Fetch::Attribute, _) => self.fetch_attribute(address),
+            (Fetch::Background, _) => Some(self.fetch_background(address)),
+            (Fetch::Sprite, _) => Some(self.chr_set(Fetch::Sprite).read(address)),
+            (Fetch::Cpu, 0x2000..=0x3eff) => self.read_nametable(address),
+            (Fetch::Cpu, _) => None
+        }
+    }
+
+    // the same banks fetch_background and ppu_fetch read from
+    fn chr_fetch_offset(&self, address: usize, fetch: Fetch) -> Option<usize> {
+        if self.chr_sprites.is_ram() {
+            return None;
+        }
+
+        let offset = match fetch {
+            Fetch::Background if self.tile_in_split => self.chr_extra.offset((address & 0x0ff8) | self.split_fine_y),
+            Fetch::Background if self.exram_mode == 1 => self.chr_extra.offset(address & 0x0fff),
+            _ => self.chr_set(fetch).offset(address)
+        };
+        Some(offset)
+    }
+
+    // the ppu stops fetching outside of rendering, which ends the frame
+    fn ppu_idle(&mut self) {
+        self.in_frame = false;
+        self.tile = 1;
+        self.nametable_matches = 0;
+    }
+
+    fn ppu_write(&mut self, address: usize, value: u8) -> bool {
+        let quadrant = (address & 0x0fff) >> 10;
+        match (self.nametable_select >> (quadrant * 2)) & 3 {
+            2 => {
+                if self.exram_mode < 2 {
+                    self.exram[address & 0x03ff] = value;
+                }
+                true
+            },
+            3 => true,
+            _ => false
+        }
+    }
+
+    fn ppu_register_write(&mut self, address: usize, value: u8) {
+        if address == 0x2000 {
+            self.sprites_8x16 = value & 0x20 != 0;
+        }
+    }
+
+    fn load_battery(&mut self) {
+        self.cart.load_prg_ram(&mut self.prg_ram);
+    }
+
+    fn save_battery(&self) {
+        self.cart.save_prg_ram(&self.prg_ram);
+    }
+
+    fn step(&mut self) {}
+}
@@ -0,0 +1,87 @@
+use crate::cartridge::Cartridge;
+use crate::cartridge::Mapper;
+use crate::cartridge::Mirror;
+use crate::cartridge::BankedMemory;
+
+pub struct GxROM {
+    cart: Cartridge,
+    // https://wiki.nesdev.com/w/index.php/GxROM
+    //
+    // the reverse of Color Dreams, the prg bank is in the high nibble
+    //
+    // --PP --CC
+    //   ||   ++- Select 8 KB CHR ROM bank for PPU $0000-$1FFF
+    //   ++------ Select 32 KB PRG ROM bank for CPU $8000-$FFFF
+    prg: BankedMemory,
+    chr: BankedMemory,
+    prg_ram: BankedMemory
+}
+
+impl GxROM {
+    pub fn new(cart: Cartridge) -> GxROM {
+        GxROM {
+            prg: cart.prg_rom(),
+            chr: cart.chr_memory(),
+            prg_ram: cart.prg_ram(),
+            cart: cart
+        }
+    }
+}
+
+impl Mapper for GxROM {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr.read(address),
+            0x6000..=0x7fff => self.prg_ram.read(address),
+            0x8000..=0xffff => self.prg.read(address),
+            _ => {
+                println!("Address out of range! 0x{:X}", address);
+                0
+            }
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.chr.write(address, value),
+            0x6000..=0x7fff => self.prg_ram.write(address, value),
+            0x8000..=0xffff => {
+                self.prg.map(0x8000, 0x8000, ((value >> 4) & 3) as usize);
+                self.chr.map(0x0000, 0x2000, (value & 3) as usize);
+            },
+            _ => println!("Address out of range! 0x{:X}", address)
+        }
+    }
+
+    fn get_mirror(&self) -> Mirror {
+        self.cart.header.mirror
+    }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
+
+    fn load_battery(&mut self) {
+        self.cart.load_prg_ram(&mut self.prg_ram);
+    }
+
+    fn save_battery(&self) {
+        self.cart.save_prg_ram(&self.prg_ram);
+    }
+
+    fn step(&mut self) {}
+}
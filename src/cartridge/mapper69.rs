@@ -0,0 +1,183 @@
+use crate::cartridge::Cartridge;
+use crate::cartridge::Mapper;
+use crate::cartridge::Mirror;
+use crate::cartridge::BankedMemory;
+use crate::cartridge::sunsoft5b_audio::Sunsoft5bAudio;
+
+// Sunsoft FME-7 and 5B, mapper 69
+//
+// https://wiki.nesdev.com/w/index.php/Sunsoft_FME-7
+//
+// all registers are accessed indirectly, a command number written to $8000-$9FFF picks which
+// register the next parameter write to $A000-$BFFF goes to. the 5B is an FME-7 with expansion
+// audio, which has its own pair of address/data ports at $C000 and $E000
+pub struct FME7 {
+    cart: Cartridge,
+    prg: BankedMemory,
+    prg_6000: BankedMemory, // either a rom bank or prg ram
+    prg_ram: BankedMemory,
+    chr: BankedMemory,
+
+    command: u8,
+    prg_6000_ram: bool,
+    prg_ram_enabled: bool,
+    mirror: Mirror,
+
+    // 16-bit counter decremented every cpu cycle, the irq fires when it wraps around from 0
+    irq_counter: u16,
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_pending: bool,
+
+    audio: Sunsoft5bAudio
+}
+
+impl FME7 {
+    pub fn new(cart: Cartridge) -> FME7 {
+        let mut prg = cart.prg_rom();
+        let last = prg.last_bank(0x2000);
+        prg.map(0xe000, 0x2000, last);
+
+        FME7 {
+            prg: prg,
+            prg_6000: BankedMemory::rom(cart.prg.clone(), 0x6000, 0x2000),
+            prg_ram: cart.prg_ram(),
+            chr: cart.chr_memory(),
+
+            command: 0,
+            prg_6000_ram: false,
+            prg_ram_enabled: false,
+            mirror: cart.header.mirror,
+
+            irq_counter: 0,
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_pending: false,
+
+            audio: Sunsoft5bAudio::new(),
+
+            cart: cart
+        }
+    }
+
+    fn write_parameter(&mut self, value: u8) {
+        match self.command {
+            0x0..=0x7 => self.chr.map(self.command as usize * 0x400, 0x400, value as usize),
+            // ERBB BBBB, ram enable, ram (1) or rom (0), rom or ram bank
+            0x8 => {
+                self.prg_ram_enabled = value & 0x80 != 0;
+                self.prg_6000_ram = value & 0x40 != 0;
+                self.prg_6000.map(0x6000, 0x2000, (value & 0x3f) as usize);
+                self.prg_ram.map(0x6000, 0x2000, (value & 0x3f) as usize);
+            },
+            0x9..=0xb => {
+                let address = 0x8000 + (self.command as usize - 0x9) * 0x2000;
+                self.prg.map(address, 0x2000, (value & 0x3f) as usize);
+            },
+            0xc => {
+                self.mirror = match value & 3 {
+                    0 => Mirror::Vertical,
+                    1 => Mirror::Horizontal,
+                    2 => Mirror::Single0,
+                    _ => Mirror::Single1
+                };
+            },
+            // C--- ---T, counter enable, irq enable. any write acknowledges the irq
+            0xd => {
+                self.irq_enabled = value & 1 != 0;
+                self.irq_counter_enabled = value & 0x80 != 0;
+                self.irq_pending = false;
+            },
+            0xe => self.irq_counter = (self.irq_counter & 0xff00) | value as u16,
+            _ => self.irq_counter = (self.irq_counter & 0x00ff) | ((value as u16) << 8)
+        }
+    }
+}
+
+impl Mapper for FME7 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr.read(address),
+            0x6000..=0x7fff => {
+                if !self.prg_6000_ram {
+                    self.prg_6000.read(address)
+                } else if self.prg_ram_enabled {
+                    self.prg_ram.read(address)
+                } else {
+                    0
+                }
+            },
+            0x8000..=0xffff => self.prg.read(address),
+            _ => {
+                println!("Address out of range! 0x{:X}", address);
+                0
+            }
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.chr.write(address, value),
+            0x6000..=0x7fff => {
+                if self.prg_6000_ram && self.prg_ram_enabled {
+                    self.prg_ram.write(address, value);
+                }
+            },
+            0x8000..=0x9fff => self.command = value & 0x0f,
+            0xa000..=0xbfff => self.write_parameter(value),
+            0xc000..=0xdfff => self.audio.write_address(value),
+            0xe000..=0xffff => self.audio.write_data(value),
+            _ => println!("Address out of range! 0x{:X}", address)
+        }
+    }
+
+    fn get_mirror(&self) -> Mirror {
+        self.mirror
+    }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x6000..=0x7fff if !self.prg_6000_ram => Some(self.prg_6000.offset(address)),
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        self.audio.output()
+    }
+
+    fn load_battery(&mut self) {
+        self.cart.load_prg_ram(&mut self.prg_ram);
+    }
+
+    fn save_battery(&self) {
+        self.cart.save_prg_ram(&self.prg_ram);
+    }
+
+    fn step(&mut self) {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xffff && self.irq_enabled {
+                self.irq_pending = true;
+            }
+        }
+
+        self.audio.clock();
+    }
+}
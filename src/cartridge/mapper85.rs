@@ -0,0 +1,201 @@
+use crate::cartridge::Cartridge;
+use crate::cartridge::Mapper;
+use crate::cartridge::Mirror;
+use crate::cartridge::BankedMemory;
+use crate::cartridge::vrc_irq::VrcIrq;
+use crate::cartridge::opll::Opll;
+
+// Konami VRC7, mapper 85
+//
+// https://wiki.nesdev.com/w/index.php/VRC7
+//
+// every register pair is split by one address line, A4 on VRC7a (Lagrange Point) and A3 on VRC7b
+// (Tiny Toon Adventures 2). without a submapper either one selects the second register
+pub struct VRC7 {
+    cart: Cartridge,
+    prg: BankedMemory,
+    chr: BankedMemory,
+    prg_ram: BankedMemory,
+    prg_ram_enabled: bool,
+
+    select_mask: usize, // address line(s) selecting the second register of a pair
+
+    prg_bank_select: [u8; 3],
+    chr_bank_select: [u8; 8],
+    mirror: Mirror,
+
+    irq: VrcIrq,
+    opll: Opll,
+    audio_silenced: bool
+}
+
+impl VRC7 {
+    pub fn new(cart: Cartridge) -> VRC7 {
+        let select_mask = match cart.header.submapper {
+            1 => 0x08, // VRC7b
+            2 => 0x10, // VRC7a
+            _ => 0x18
+        };
+
+        let mut vrc = VRC7 {
+            prg: cart.prg_rom(),
+            chr: cart.chr_memory(),
+            prg_ram: cart.prg_ram(),
+            prg_ram_enabled: false,
+
+            select_mask: select_mask,
+
+            prg_bank_select: [0; 3],
+            chr_bank_select: [0; 8],
+            mirror: cart.header.mirror,
+
+            irq: VrcIrq::new(),
+            opll: Opll::new(),
+            audio_silenced: false,
+
+            cart: cart
+        };
+        vrc.update_banks();
+
+        vrc
+    }
+
+    fn update_banks(&mut self) {
+        for i in 0..3 {
+            self.prg.map(0x8000 + i * 0x2000, 0x2000, self.prg_bank_select[i] as usize);
+        }
+        let last = self.prg.last_bank(0x2000);
+        self.prg.map(0xe000, 0x2000, last);
+
+        for i in 0..8 {
+            self.chr.map(i * 0x400, 0x400, self.chr_bank_select[i] as usize);
+        }
+    }
+
+    fn write_register(&mut self, address: usize, value: u8) {
+        let second = address & self.select_mask != 0;
+        match (address & 0xf000, second) {
+            (0x8000, false) => self.prg_bank_select[0] = value & 0x3f,
+            (0x8000, true) => self.prg_bank_select[1] = value & 0x3f,
+            (0x9000, false) => self.prg_bank_select[2] = value & 0x3f,
+            (0xa000..=0xd000, _) => {
+                let slot = ((address & 0xf000) - 0xa000) / 0x1000 * 2 + second as usize;
+                self.chr_bank_select[slot] = value;
+            },
+            (0xe000, false) => self.write_control(value),
+            (0xe000, true) => self.irq.write_latch(value),
+            (0xf000, false) => self.irq.write_control(value),
+            (0xf000, true) => self.irq.acknowledge(),
+            _ => ()
+        }
+
+        self.update_banks();
+    }
+
+    // RS-- --MM
+    // |      ++- mirroring
+    // |+-------- silence and reset the expansion audio
+    // +--------- prg ram enable
+    fn write_control(&mut self, value: u8) {
+        self.mirror = match value & 3 {
+            0 => Mirror::Vertical,
+            1 => Mirror::Horizontal,
+            2 => Mirror::Single0,
+            _ => Mirror::Single1
+        };
+
+        self.audio_silenced = value & 0x40 != 0;
+        if self.audio_silenced {
+            self.opll.reset();
+        }
+
+        self.prg_ram_enabled = value & 0x80 != 0;
+    }
+}
+
+impl Mapper for VRC7 {
+    fn read(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.chr.read(address),
+            0x6000..=0x7fff => {
+                if self.prg_ram_enabled {
+                    self.prg_ram.read(address)
+                } else {
+                    0
+                }
+            },
+            0x8000..=0xffff => self.prg.read(address),
+            _ => {
+                println!("Address out of range! 0x{:X}", address);
+                0
+            }
+        }
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x0000..=0x1fff => self.chr.write(address, value),
+            0x6000..=0x7fff => {
+                if self.prg_ram_enabled {
+                    self.prg_ram.write(address, value);
+                }
+            },
+            // the audio ports are only decoded on VRC7a, at $9010 and $9030
+            0x8000..=0xffff => match address & 0xf030 {
+                0x9010 => self.opll.write_address(value),
+                0x9030 => self.opll.write_data(value),
+                _ => self.write_register(address, value)
+            },
+            _ => println!("Address out of range! 0x{:X}", address)
+        }
+    }
+
+    fn get_mirror(&self) -> Mirror {
+        self.mirror
+    }
+
+    fn cartridge(&self) -> &Cartridge {
+        &self.cart
+    }
+
+    fn prg_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x8000..=0xffff => Some(self.prg.offset(address)),
+            _ => None
+        }
+    }
+
+    fn chr_rom_offset(&self, address: usize) -> Option<usize> {
+        match address {
+            0x0000..=0x1fff if !self.chr.is_ram() => Some(self.chr.offset(address)),
+            _ => None
+        }
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending
+    }
+
+    fn audio_output(&self) -> f32 {
+        if self.audio_silenced {
+            0.0
+        } else {
+            self.opll.output()
+        }
+    }
+
+    fn load_battery(&mut self) {
+        self.cart.load_prg_ram(&mut self.prg_ram);
+    }
+
+    fn save_battery(&self) {
+        self.cart.save_prg_ram(&self.prg_ram);
+    }
+
+    fn step(&mut self) {
+        self.irq.clock();
+        if !self.audio_silenced {
+            self.opll.clock();
+        }
+    }
+}
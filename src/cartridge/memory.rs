@@ -0,0 +1,172 @@
+// every window is mapped in pages of this size, which is the smallest bank size any mapper uses
+const PAGE_SIZE: usize = 0x400; // 1 KiB
+
+// a flat rom/ram buffer mapped into a cpu or ppu address window through switchable banks
+//
+// mappers switch banks of 1, 2, 4, 8, 16 or 32 KiB, often mixing sizes within the same window
+// (e.g. MMC1's 4 KiB chr mode). internally the window is always split into 1 KiB pages that each
+// point somewhere into the buffer, so mapping a bank of any size is just updating a few pages.
+//
+// bank numbers wrap around the size of the buffer, just like the unconnected upper bank bits on
+// real boards, so a game writing an out of range bank never indexes out of bounds
+#[derive(Debug)]
+pub struct BankedMemory {
+    data: Vec<u8>,
+    base: usize, // first address of the window, e.g. 0x8000 for prg rom
+    pages: Vec<usize>, // offset into data for every page of the window
+    writable: bool
+}
+
+impl BankedMemory {
+    // the window starts out mapped linearly, so small buffers are mirrored across it
+    pub fn new(data: Vec<u8>, base: usize, window_size: usize, writable: bool) -> BankedMemory {
+        let len = data.len().max(1);
+        let pages = (0..window_size / PAGE_SIZE).map(|i| (i * PAGE_SIZE) % len).collect();
+
+        BankedMemory {
+            data: data,
+            base: base,
+            pages: pages,
+            writable: writable
+        }
+    }
+
+    pub fn rom(data: Vec<u8>, base: usize, window_size: usize) -> BankedMemory {
+        BankedMemory::new(data, base, window_size, false)
+    }
+
+    pub fn ram(size: usize, base: usize, window_size: usize) -> BankedMemory {
+        BankedMemory::new(vec![0; size], base, window_size, true)
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    pub fn is_ram(&self) -> bool {
+        self.writable
+    }
+
+    pub fn bank_count(&self, bank_size: usize) -> usize {
+        (self.data.len() / bank_size).max(1)
+    }
+
+    pub fn last_bank(&self, bank_size: usize) -> usize {
+        self.bank_count(bank_size) - 1
+    }
+
+    // map a bank of `bank_size` bytes to the given address
+    pub fn map(&mut self, address: usize, bank_size: usize, bank: usize) {
+        let len = self.data.len().max(1);
+        let bank_offset = (bank % self.bank_count(bank_size)) * bank_size;
+        let first_page = (address - self.base) / PAGE_SIZE;
+
+        for i in 0..bank_size / PAGE_SIZE {
+            if let Some(page) = self.pages.get_mut(first_page + i) {
+                *page = (bank_offset + i * PAGE_SIZE) % len;
+            }
+        }
+    }
+
+    // offset into the underlying buffer that an address currently maps to
+    pub fn offset(&self, address: usize) -> usize {
+        let index = address - self.base;
+        self.pages[index / PAGE_SIZE] + (index % PAGE_SIZE)
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        if self.data.is_empty() {
+            return 0;
+        }
+
+        self.data[self.offset(address)]
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        if self.writable && !self.data.is_empty() {
+            let offset = self.offset(address);
+            self.data[offset] = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 banks of 8 KiB, each filled with its bank number
+    fn numbered_rom() -> BankedMemory {
+        let data = (0..4).flat_map(|bank| vec![bank as u8; 0x2000]).collect();
+        BankedMemory::rom(data, 0x8000, 0x8000)
+    }
+
+    #[test]
+    fn starts_out_mapped_linearly() {
+        let rom = numbered_rom();
+        assert_eq!(rom.read(0x8000), 0);
+        assert_eq!(rom.read(0xa000), 1);
+        assert_eq!(rom.read(0xffff), 3);
+        assert_eq!(rom.offset(0xc123), 0x4123);
+    }
+
+    #[test]
+    fn small_buffers_are_mirrored() {
+        let rom = BankedMemory::rom(vec![1, 2, 3, 4], 0, 0x2000);
+        assert_eq!(rom.read(0x1001), 2);
+
+        let rom = BankedMemory::rom(vec![0x42; 0x4000], 0x8000, 0x8000);
+        assert_eq!(rom.offset(0xc000), 0);
+    }
+
+    #[test]
+    fn maps_banks_of_any_size() {
+        let mut rom = numbered_rom();
+
+        rom.map(0x8000, 0x2000, 3);
+        assert_eq!(rom.read(0x8000), 3);
+        assert_eq!(rom.read(0x9fff), 3);
+        assert_eq!(rom.read(0xa000), 1);
+
+        // a 16 KiB bank covers two of the 8 KiB ones
+        rom.map(0xc000, 0x4000, 0);
+        assert_eq!(rom.read(0xc000), 0);
+        assert_eq!(rom.read(0xe000), 1);
+
+        rom.map(0x8000, 0x400, 9);
+        assert_eq!(rom.offset(0x8000), 9 * 0x400);
+    }
+
+    #[test]
+    fn bank_numbers_wrap_around() {
+        let mut rom = numbered_rom();
+        assert_eq!(rom.bank_count(0x2000), 4);
+        assert_eq!(rom.last_bank(0x4000), 1);
+
+        rom.map(0x8000, 0x2000, 6);
+        assert_eq!(rom.read(0x8000), 2);
+    }
+
+    #[test]
+    fn only_ram_is_writable() {
+        let mut rom = numbered_rom();
+        rom.write(0x8000, 0xff);
+        assert_eq!(rom.read(0x8000), 0);
+
+        let mut ram = BankedMemory::ram(0x4000, 0x6000, 0x2000);
+        ram.map(0x6000, 0x2000, 1);
+        ram.write(0x6001, 0xff);
+        assert_eq!(ram.data()[0x2001], 0xff);
+        assert!(ram.is_ram());
+    }
+
+    #[test]
+    fn empty_buffers_read_as_zero() {
+        let mut ram = BankedMemory::ram(0, 0x6000, 0x2000);
+        ram.write(0x7000, 0xff);
+        assert_eq!(ram.read(0x7000), 0);
+    }
+}
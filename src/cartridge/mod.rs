@@ -1,67 +1,228 @@
+mod memory;
+mod database;
+mod unif;
+mod vrc_irq;
+mod vrc6_audio;
+mod opll;
+mod sunsoft5b_audio;
+mod namco163_audio;
+mod eeprom;
+mod fds_audio;
 mod mapper0;
 mod mapper1;
 mod mapper2;
 mod mapper3;
+mod mapper5;
+mod mapper11;
+mod mapper16;
+mod mapper19;
+mod mapper20;
+mod mapper21;
+mod mapper24;
+mod mapper66;
+mod mapper69;
+mod mapper85;
+
+use memory::BankedMemory;
+use database::Database;
 
 use mapper0::NROM;
 use mapper1::MMC1;
 use mapper2::UxROM;
 use mapper3::CNROM;
+use mapper5::MMC5;
+use mapper11::ColorDreams;
+use mapper16::BandaiFCG;
+use mapper19::Namco163;
+use mapper20::FDS;
+use mapper21::VRC4;
+use mapper24::VRC6;
+use mapper66::GxROM;
+use mapper69::FME7;
+use mapper85::VRC7;
+
+use crate::checksum::{crc32, sha1};
+use crate::data_dir::{default_data_dir, game_dir};
+use crate::region::Region;
 
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Mirror {
     Horizontal,
     Vertical,
     Single0,
     Single1,
+    Custom([usize; 4]), // nametable (0 or 1) for each of the four quadrants
     // Four,
 }
 
+// what the ppu is reading, for mappers that look at its fetch pattern
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Fetch {
+    Nametable,
+    Attribute,
+    Background, // background pattern
+    Sprite, // sprite pattern
+    Cpu // through $2007
+}
+
 pub trait Mapper {
     fn read(&self, address: usize) -> u8;
     fn write(&mut self, address: usize, value: u8);
     fn get_mirror(&self) -> Mirror;
+    fn cartridge(&self) -> &Cartridge;
+
+    // translate a cpu/ppu address into an offset into prg/chr rom through the current bank
+    // layout, None if the address doesn't map to rom
+    fn prg_rom_offset(&self, address: usize) -> Option<usize>;
+    fn chr_rom_offset(&self, address: usize) -> Option<usize>;
     fn load_battery(&mut self);
     fn save_battery(&self);
+
+    // called once every cpu cycle
     fn step(&mut self);
+
+    // level of the cartridge's irq line, the cpu takes the interrupt for as long as this is held
+    // and interrupts aren't masked
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
+    // a cpu read for the debugger, without side effects like acknowledging an irq
+    fn peek(&self, address: usize) -> u8 {
+        self.read(address)
+    }
+
+    // called for every ppu read below $3F00, returning Some replaces the value the ppu would
+    // have read from chr or its own nametables
+    fn ppu_fetch(&mut self, _address: usize, _fetch: Fetch) -> Option<u8> {
+        None
+    }
+
+    // chr rom offset of a pattern fetch, for mappers that pick the chr bank by what it's for
+    fn chr_fetch_offset(&self, address: usize, _fetch: Fetch) -> Option<usize> {
+        self.chr_rom_offset(address)
+    }
+
+    // called once the ppu hasn't fetched anything for 3 cpu cycles, which it only does outside of
+    // rendering. MMC5 takes this as the end of the frame
+    fn ppu_idle(&mut self) {}
+
+    // called for every ppu nametable write, true if the mapper handled it itself
+    fn ppu_write(&mut self, _address: usize, _value: u8) -> bool {
+        false
+    }
+
+    // called for cpu writes to PPUCTRL ($2000) and PPUMASK ($2001)
+    fn ppu_register_write(&mut self, _address: usize, _value: u8) {}
+
+    // disk system hotkeys, flip to the next disk side or eject and reinsert the disk
+    fn switch_disk_side(&mut self) {}
+    fn eject_disk(&mut self) {}
+
+    // current level of the cartridge's expansion audio, sampled once every cpu cycle
+    //
+    // 1.0 is about as loud as the 2A03's own channels at full volume, boards without expansion
+    // audio stay silent
+    fn audio_output(&self) -> f32 {
+        0.0
+    }
 }
 
 #[derive(Debug)]
 pub struct NesHeader {
     file_path: String,
+    submapper: u8,
     prg_rom_size: usize,
     chr_rom_size: usize,
     mirror: Mirror,
     battery_backed_ram: bool,
+    prg_ram_size: Option<usize>, // unknown for most iNES 1.0 headers
+    region: Region,
     trainer: bool,
-    ignore_mirror: bool
+    ignore_mirror: bool,
+    nes2: bool
 }
 
 #[derive(Debug)]
 pub struct Cartridge {
     header: NesHeader,
-    prg: Vec<Vec<u8>>, // chunks of prg rom (16 KiB chunks)
-    chr: Vec<Vec<u8>>, // chunks of chr rom (8 KiB chunks)
-    mapper: u8
+    prg: Vec<u8>, // prg rom, a multiple of 16 KiB
+    chr: Vec<u8>, // chr rom, a multiple of 8 KiB (empty for boards with chr ram)
+    trainer: Vec<u8>, // 512 bytes loaded to $7000 at power on (empty without one)
+    disk: Vec<u8>, // disk system sides, in FDS_SIDE_SIZE units (empty for cartridges)
+    mapper: u16,
+    game_dir: PathBuf, // the game's folder in the data directory, see data_dir.rs
+
+    // contents of the .sav file as last read or written, so saving unchanged battery ram is free
+    saved_battery: RefCell<Vec<u8>>
+}
+
+// the blocks of one disk side in a .fds image, without the gaps or crcs
+pub const FDS_SIDE_SIZE: usize = 65500;
+
+// settings from the command line for loading a rom
+#[derive(Default)]
+pub struct LoadOptions {
+    pub fds_bios: Option<String>, // the disk system BIOS, only needed to load disk images
+    pub patch: Option<String>, // instead of a patch found next to the rom
+    pub database: Option<String>, // header database, used along with the embedded one
+    pub data_dir: Option<String>, // instead of the default data directory
+    pub region: Option<Region> // instead of the one from the header or database
 }
 
-pub fn get_mapper(buffer: Vec<u8>, file_path: String) -> Rc<RefCell<dyn Mapper>> {
-    let cart = Cartridge::new(buffer, file_path);
-    match cart.mapper {
+pub fn get_mapper(buffer: Vec<u8>, file_path: String, options: &LoadOptions) -> Rc<RefCell<dyn Mapper>> {
+    let mut cart = Cartridge::new(buffer, file_path);
+    cart.correct_header(&Database::load(options.database.as_deref()));
+
+    let data_dir = options.data_dir.as_ref().map(PathBuf::from).unwrap_or_else(default_data_dir);
+    cart.game_dir = game_dir(&data_dir, &cart.rom_data());
+
+    let mapper: Rc<RefCell<dyn Mapper>> = match cart.mapper {
         0 => Rc::new(RefCell::new(NROM::new(cart))),
         1 => Rc::new(RefCell::new(MMC1::new(cart))),
         2 => Rc::new(RefCell::new(UxROM::new(cart))),
         3 => Rc::new(RefCell::new(CNROM::new(cart))),
-        _ => panic!("Unimplemented mapper!")
-    }
+        5 => Rc::new(RefCell::new(MMC5::new(cart))),
+        11 => Rc::new(RefCell::new(ColorDreams::new(cart))),
+        16 | 153 | 157 | 159 => Rc::new(RefCell::new(BandaiFCG::new(cart))),
+        19 => Rc::new(RefCell::new(Namco163::new(cart))),
+        20 => Rc::new(RefCell::new(FDS::new(cart, options.fds_bios.clone()))),
+        21 | 22 | 23 | 25 => Rc::new(RefCell::new(VRC4::new(cart))),
+        24 | 26 => Rc::new(RefCell::new(VRC6::new(cart))),
+        66 => Rc::new(RefCell::new(GxROM::new(cart))),
+        69 => Rc::new(RefCell::new(FME7::new(cart))),
+        85 => Rc::new(RefCell::new(VRC7::new(cart))),
+        _ => panic!("Unimplemented mapper! {}", cart.mapper)
+    };
+    mapper.borrow_mut().load_battery();
+
+    mapper
 }
 
 impl Cartridge {
-    fn new(buffer: Vec<u8>, file_path: String) -> Cartridge {
+    pub fn new(buffer: Vec<u8>, file_path: String) -> Cartridge {
         let ines_signature = [0x4e, 0x45, 0x53, 0x1a];
+        let fds_signature = [0x46, 0x44, 0x53, 0x1a];
+
+        // https://wiki.nesdev.com/w/index.php/FDS_file_format
+        //
+        // .fds images are either a 16 byte header followed by the sides, or just the sides, which
+        // start with the disk info block
+        if buffer.starts_with(&fds_signature) {
+            return Cartridge::from_disk(&buffer[0x10..], file_path);
+        }
+        if buffer.starts_with(b"\x01*NINTENDO-HVC*") {
+            return Cartridge::from_disk(&buffer, file_path);
+        }
+
+        if buffer.starts_with(b"UNIF") {
+            return unif::parse(&buffer, file_path);
+        }
 
         // https://wiki.nesdev.com/w/index.php/INES
         if buffer[0..4] != ines_signature {
@@ -71,45 +232,298 @@ impl Cartridge {
         let flags6 = buffer[6];
         let flags7 = buffer[7];
 
-        let mapper = (flags7 & 0xf0) | flags6 >> 4;
+        // https://wiki.nesdev.com/w/index.php/NES_2.0
+        //
+        // NES 2.0 headers extend the mapper number to 12 bits and add a submapper for boards that
+        // share a mapper number but differ in wiring
+        let nes2 = flags7 & 0x0c == 0x08;
+
+        let mut mapper = ((flags7 & 0xf0) | flags6 >> 4) as u16;
+        let mut submapper = 0;
+        if nes2 {
+            mapper |= ((buffer[8] & 0x0f) as u16) << 8;
+            submapper = buffer[8] >> 4;
+        }
+
         let mirror = if flags6 & 0x1 != 0 { Mirror::Vertical } else { Mirror::Horizontal };
 
+        // old tools wrote their name over the end of iNES 1.0 headers (e.g. "DiskDude!"), so
+        // bytes 8 and 9 are only trusted if bytes 12-15 are still zero
+        let clean_header = buffer[12..16].iter().all(|&b| b == 0);
+
+        // NES 2.0 gives the volatile and battery backed sizes as shift counts of 64 bytes, iNES 1.0
+        // in 8 KiB units where 0 usually just means nobody filled it in
+        let prg_ram_size = if nes2 {
+            let shift_size = |shift: u8| if shift == 0 { 0 } else { 64 << shift };
+            Some(shift_size(buffer[10] & 0x0f) + shift_size(buffer[10] >> 4))
+        } else if clean_header && buffer[8] != 0 {
+            Some(buffer[8] as usize * 0x2000)
+        } else {
+            None
+        };
+
+        // NES 2.0 has the cpu/ppu timing in byte 12 (multi-region games run as NTSC), iNES 1.0 a
+        // PAL bit in byte 9
+        let region = if nes2 {
+            match buffer[12] & 3 {
+                1 => Region::PAL,
+                3 => Region::Dendy,
+                _ => Region::NTSC
+            }
+        } else if clean_header && buffer[9] & 1 != 0 {
+            Region::PAL
+        } else {
+            Region::NTSC
+        };
+
         let header = NesHeader {
             file_path: file_path,
+            submapper: submapper,
             prg_rom_size: buffer[4] as usize,
             chr_rom_size: buffer[5] as usize,
             mirror: mirror,
             battery_backed_ram: flags6 & 0x2 != 0,
+            prg_ram_size: prg_ram_size,
+            region: region,
             trainer: flags6 & 0x4 != 0,
-            ignore_mirror: flags6 & 0x8 != 0
+            ignore_mirror: flags6 & 0x8 != 0,
+            nes2: nes2
         };
 
-        //let prg_ram_size = buffer[8];
-
         let mut cart = Cartridge {
             header: header,
             prg: Vec::new(),
             chr: Vec::new(),
-            mapper: mapper
+            trainer: Vec::new(),
+            disk: Vec::new(),
+            mapper: mapper,
+            game_dir: PathBuf::new(),
+            saved_battery: RefCell::new(Vec::new())
         };
 
         let prg_chunk = 1 << 14; // 16 KiB
         let chr_chunk = 1 << 13; // 8 KiB
 
+        // the trainer sits between the header and prg rom
         let prg_offset = 0x10 + if cart.header.trainer { 0x200 } else { 0 };
+        if cart.header.trainer {
+            cart.trainer = buffer[0x10..0x210].to_vec();
+        }
+
         let chr_offset = prg_offset + (cart.header.prg_rom_size * prg_chunk);
 
-        for i in 0..cart.header.prg_rom_size {
-            let offset = prg_offset + (i * prg_chunk);
-            cart.prg.push(buffer[offset..(offset + prg_chunk)].to_vec());
+        cart.prg = buffer[prg_offset..(prg_offset + cart.header.prg_rom_size * prg_chunk)].to_vec();
+        cart.chr = buffer[chr_offset..(chr_offset + cart.header.chr_rom_size * chr_chunk)].to_vec();
+
+        println!("{:x?}", cart);
+        cart
+    }
+
+    // a disk system image, given to the FDS as mapper 20. any partial side at the end is dropped
+    fn from_disk(data: &[u8], file_path: String) -> Cartridge {
+        let sides = data.len() / FDS_SIDE_SIZE;
+        if sides == 0 {
+            panic!("Incorrect FDS image size! {} bytes", data.len());
+        }
+
+        let header = NesHeader {
+            file_path: file_path,
+            submapper: 0,
+            prg_rom_size: 0,
+            chr_rom_size: 0,
+            mirror: Mirror::Horizontal,
+            battery_backed_ram: true,
+            prg_ram_size: None,
+            region: Region::NTSC,
+            trainer: false,
+            ignore_mirror: false,
+            nes2: false
+        };
+
+        println!("FDS image with {} disk sides", sides);
+        Cartridge {
+            header: header,
+            prg: Vec::new(),
+            chr: Vec::new(),
+            trainer: Vec::new(),
+            disk: data[..sides * FDS_SIDE_SIZE].to_vec(),
+            mapper: 20,
+            game_dir: PathBuf::new(),
+            saved_battery: RefCell::new(Vec::new())
         }
+    }
 
-        for i in 0..cart.header.chr_rom_size {
-            let offset = chr_offset + (i * chr_chunk);
-            cart.chr.push(buffer[offset..(offset + chr_chunk)].to_vec());
+    // iNES 1.0 headers are often wrong, so the ones of known roms are corrected from the database.
+    // NES 2.0 headers are trusted
+    fn correct_header(&mut self, database: &Database) {
+        if self.header.nes2 || !self.disk.is_empty() {
+            return;
         }
 
-        println!("{:x?}", cart);
-        cart
+        let rom = self.rom_data();
+        let game = match database.find(crc32(&rom), &sha1(&rom)) {
+            Some(game) => game,
+            None => return
+        };
+
+        if let Some(mapper) = game.mapper {
+            if mapper != self.mapper {
+                println!("Corrected mapper: {} -> {} ({})", self.mapper, mapper, game.name);
+                self.mapper = mapper;
+            }
+        }
+        if let Some(submapper) = game.submapper {
+            if submapper != self.header.submapper {
+                println!("Corrected submapper: {} -> {} ({})", self.header.submapper, submapper, game.name);
+                self.header.submapper = submapper;
+            }
+        }
+        if let Some(mirror) = game.mirror {
+            if mirror != self.header.mirror {
+                println!("Corrected mirroring: {:?} -> {:?} ({})", self.header.mirror, mirror, game.name);
+                self.header.mirror = mirror;
+            }
+        }
+        if let Some(battery) = game.battery {
+            if battery != self.header.battery_backed_ram {
+                println!("Corrected battery: {} -> {} ({})", self.header.battery_backed_ram, battery, game.name);
+                self.header.battery_backed_ram = battery;
+            }
+        }
+        if let Some(region) = game.region {
+            if region != self.header.region {
+                println!("Corrected region: {:?} -> {:?} ({})", self.header.region, region, game.name);
+                self.header.region = region;
+            }
+        }
+        if let Some(prg_ram_size) = game.prg_ram_size {
+            if Some(prg_ram_size) != self.header.prg_ram_size {
+                println!("Corrected prg ram size: {:?} -> {} ({})", self.header.prg_ram_size, prg_ram_size, game.name);
+                self.header.prg_ram_size = Some(prg_ram_size);
+            }
+        }
+    }
+
+    // everything but the header, for identifying the game
+    fn rom_data(&self) -> Vec<u8> {
+        self.prg.iter().chain(self.chr.iter()).chain(self.disk.iter()).copied().collect()
+    }
+
+    pub fn region(&self) -> Region {
+        self.header.region
+    }
+
+    pub fn prg_rom_len(&self) -> usize {
+        self.header.prg_rom_size * 0x4000
+    }
+
+    pub fn chr_rom_len(&self) -> usize {
+        self.header.chr_rom_size * 0x2000
+    }
+
+    pub fn prg_bank_count(&self) -> usize {
+        self.header.prg_rom_size
+    }
+
+    // 16 KiB prg bank, out of range bank numbers wrap around like they would on the mapper
+    pub fn prg_bank(&self, bank: usize) -> &[u8] {
+        let offset = (bank % self.prg_bank_count()) * 0x4000;
+        &self.prg[offset..(offset + 0x4000)]
+    }
+
+    pub fn battery_path(&self) -> PathBuf {
+        self.game_dir.join("battery.sav")
+    }
+
+    // prg ram mapped into $6000-$7FFF, 8 KiB unless the header says otherwise
+    pub fn prg_ram(&self) -> BankedMemory {
+        self.prg_ram_window(0x2000, 0x2000)
+    }
+
+    // prg ram for mappers that can map it outside of $6000-$7FFF too, the header size is used if
+    // it has one. the trainer is at $7000-$71FF at power on, so a game with one always gets at
+    // least 8 KiB, even if its NES 2.0 header says there's no prg ram
+    pub fn prg_ram_window(&self, default_size: usize, window_size: usize) -> BankedMemory {
+        let mut size = self.header.prg_ram_size.unwrap_or(default_size);
+        if !self.trainer.is_empty() {
+            size = size.max(0x2000);
+        }
+        let mut ram = BankedMemory::ram(size, 0x6000, window_size);
+        for (i, &value) in self.trainer.iter().enumerate() {
+            ram.write(0x7000 + i, value);
+        }
+        ram
+    }
+
+    // the path and contents of the .sav file, if the game has one
+    //
+    // saves used to be <rom name>.sav next to the rom, which is still read if the data directory
+    // doesn't have one yet. it's then written to the data directory at the next save
+    pub fn read_battery(&self) -> Option<(PathBuf, Vec<u8>)> {
+        let save = self.battery_path();
+        if let Ok(data) = fs::read(&save) {
+            *self.saved_battery.borrow_mut() = data.clone();
+            return Some((save, data));
+        }
+
+        let old_save = Path::new(&self.header.file_path).with_extension("sav");
+        let data = fs::read(&old_save).ok()?;
+        Some((old_save, data))
+    }
+
+    // battery ram is saved every few seconds and from the panic hook, so nothing is written unless
+    // it changed, and a crash mid-write can't leave a truncated .sav behind: the data goes to a
+    // temporary file that then replaces the old save
+    pub fn write_battery(&self, data: &[u8]) {
+        if *self.saved_battery.borrow() == data {
+            return;
+        }
+
+        let save = self.battery_path();
+        let temp = save.with_extension("sav.tmp");
+        let result = fs::create_dir_all(&self.game_dir)
+            .and_then(|_| fs::write(&temp, data))
+            .and_then(|_| fs::rename(&temp, &save));
+        match result {
+            Ok(()) => {
+                *self.saved_battery.borrow_mut() = data.to_vec();
+                println!("Wrote battery save to: {:?}", save);
+            },
+            Err(e) => println!("Cannot write battery save! {:?} {}", save, e)
+        }
+    }
+
+    // battery backed prg ram is restored from the .sav file, as long as it's the same size
+    pub fn load_prg_ram(&self, ram: &mut BankedMemory) {
+        if !self.header.battery_backed_ram {
+            return;
+        }
+
+        if let Some((save, data)) = self.read_battery() {
+            if data.len() == ram.data().len() {
+                ram.data_mut().copy_from_slice(&data);
+                println!("Loaded battery save from: {:?}", save);
+            }
+        }
+    }
+
+    pub fn save_prg_ram(&self, ram: &BankedMemory) {
+        if self.header.battery_backed_ram && !ram.data().is_empty() {
+            self.write_battery(ram.data());
+        }
+    }
+
+    // prg rom mapped into $8000-$FFFF, mirrored until the mapper switches banks
+    pub fn prg_rom(&self) -> BankedMemory {
+        BankedMemory::rom(self.prg.clone(), 0x8000, 0x8000)
+    }
+
+    // chr rom mapped into $0000-$1FFF, boards without chr rom get 8 KiB of chr ram instead
+    pub fn chr_memory(&self) -> BankedMemory {
+        if self.chr.is_empty() {
+            BankedMemory::ram(0x2000, 0, 0x2000)
+        } else {
+            BankedMemory::rom(self.chr.clone(), 0, 0x2000)
+        }
     }
 }
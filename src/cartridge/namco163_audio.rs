@@ -0,0 +1,114 @@
+use std::cell::Cell;
+
+// Namco 163 expansion audio, up to 8 wavetable channels
+//
+// https://wiki.nesdev.com/w/index.php/Namco_163_audio
+//
+// the waveforms and the channel registers share the chip's 128 bytes of internal ram, which the
+// cpu accesses through a data port at $4800 and an address port at $F800. channel registers sit
+// at the top of ram, channel 7 at $78-$7F, channel 6 at $70-$77 and so on:
+//
+// +0  frequency low       +1  phase low
+// +2  frequency mid       +3  phase mid
+// +4  LLLL LLFF, wave length (256 - L * 4 samples) and frequency high
+// +5  phase high          +6  wave address, in 4-bit samples
+// +7  ---- VVVV, volume. $7F also holds the number of enabled channels - 1 in bits 4-6
+//
+// the chip updates one channel every 15 cpu cycles and outputs only that channel until the next
+// update, so with more channels enabled each one plays at a lower rate and quieter. averaging the
+// channels' last outputs is what that multiplexed signal sounds like after the console's filtering
+pub struct Namco163Audio {
+    pub ram: [u8; 0x80],
+    address: Cell<u8>, // IAAA AAAA, auto increment and address
+    outputs: [i16; 8],
+
+    current_channel: usize,
+    cycles: u8
+}
+
+const CPU_CYCLES_PER_CHANNEL: u8 = 15;
+
+impl Namco163Audio {
+    pub fn new() -> Namco163Audio {
+        Namco163Audio {
+            ram: [0; 0x80],
+            address: Cell::new(0),
+            outputs: [0; 8],
+
+            current_channel: 7,
+            cycles: 0
+        }
+    }
+
+    pub fn write_address(&mut self, value: u8) {
+        self.address.set(value);
+    }
+
+    fn next_address(&self) -> usize {
+        let address = self.address.get();
+        if address & 0x80 != 0 {
+            self.address.set(0x80 | (address.wrapping_add(1) & 0x7f));
+        }
+
+        (address & 0x7f) as usize
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.ram[self.next_address()]
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        let address = self.next_address();
+        self.ram[address] = value;
+    }
+
+    fn enabled_channels(&self) -> usize {
+        ((self.ram[0x7f] >> 4) & 7) as usize + 1
+    }
+
+    // called once per cpu cycle
+    pub fn clock(&mut self) {
+        self.cycles += 1;
+        if self.cycles < CPU_CYCLES_PER_CHANNEL {
+            return;
+        }
+        self.cycles = 0;
+
+        self.update_channel(self.current_channel);
+
+        // channels are updated from 7 down to the lowest enabled one
+        let lowest = 8 - self.enabled_channels();
+        self.current_channel = if self.current_channel <= lowest { 7 } else { self.current_channel - 1 };
+    }
+
+    fn update_channel(&mut self, channel: usize) {
+        let base = 0x40 + channel * 8;
+        let registers = &self.ram[base..base + 8];
+
+        let frequency = registers[0] as u32 | (registers[2] as u32) << 8 | ((registers[4] & 3) as u32) << 16;
+        let phase = registers[1] as u32 | (registers[3] as u32) << 8 | (registers[5] as u32) << 16;
+        let length = 256 - (registers[4] & 0xfc) as u32;
+        let wave_address = registers[6] as u32;
+        let volume = (registers[7] & 0x0f) as i16;
+
+        // the phase is 8.16 fixed point, in samples
+        let phase = (phase + frequency) % (length << 16);
+        self.ram[base + 1] = phase as u8;
+        self.ram[base + 3] = (phase >> 8) as u8;
+        self.ram[base + 5] = (phase >> 16) as u8;
+
+        // two 4-bit samples per byte, the low nibble first
+        let sample_address = ((phase >> 16) + wave_address) & 0xff;
+        let byte = self.ram[(sample_address / 2) as usize];
+        let sample = if sample_address & 1 == 0 { byte & 0x0f } else { byte >> 4 };
+
+        self.outputs[channel] = (sample as i16 - 8) * volume;
+    }
+
+    pub fn output(&self) -> f32 {
+        let enabled = self.enabled_channels();
+        let sum: i16 = self.outputs[8 - enabled..].iter().sum();
+
+        sum as f32 / enabled as f32 / 120.0 * 0.5
+    }
+}
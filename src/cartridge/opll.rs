@@ -0,0 +1,430 @@
+// the VRC7's FM synthesizer, a cut down Yamaha YM2413 (OPLL) with 6 channels and no rhythm mode
+//
+// https://wiki.nesdev.com/w/index.php/VRC7_audio
+// https://www.smspower.org/maxim/Documents/YM2413ApplicationManual
+//
+// every channel is a modulator operator feeding the phase of a carrier operator. an operator is a
+// sine wave (optionally with its negative half cut off) shaped by an envelope, and its settings
+// come from one of 15 built-in instrument patches or the single user defined patch.
+//
+// this isn't a bit exact emulation of the chip's log/exp tables. the envelope, key scaling and
+// lfo are modeled in decibels with the rates and depths from the datasheet, which is close enough
+// to sound right without needing the original roms
+use std::f32::consts::PI;
+
+const CHANNELS: usize = 6;
+
+// the chip runs off its own 3.58 MHz crystal and produces a sample every 72 clocks, which works
+// out to exactly one sample every 36 nes cpu cycles
+const CPU_CYCLES_PER_SAMPLE: u8 = 36;
+const SAMPLE_RATE: f32 = 3_579_545.0 / 72.0;
+
+// built-in patches, in the same 8 byte layout as registers $00-$07. patch 0 is the user patch
+//
+// dumped from the VRC7 die, they differ from the YM2413's
+const PATCHES: [[u8; 8]; 16] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x03, 0x21, 0x05, 0x06, 0xe8, 0x81, 0x42, 0x27], // buzzy bell
+    [0x13, 0x41, 0x14, 0x0d, 0xd8, 0xf6, 0x23, 0x12], // guitar
+    [0x11, 0x11, 0x08, 0x08, 0xfa, 0xb2, 0x20, 0x12], // wurly
+    [0x31, 0x61, 0x0c, 0x07, 0xa8, 0x64, 0x61, 0x27], // flute
+    [0x32, 0x21, 0x1e, 0x06, 0xe1, 0x76, 0x01, 0x28], // clarinet
+    [0x02, 0x01, 0x06, 0x00, 0xa3, 0xe2, 0xf4, 0xf4], // synth
+    [0x21, 0x61, 0x1d, 0x07, 0x82, 0x81, 0x11, 0x07], // trumpet
+    [0x23, 0x21, 0x22, 0x17, 0xa2, 0x72, 0x01, 0x17], // organ
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // bells
+    [0xb5, 0x01, 0x0f, 0x0f, 0xa8, 0xa5, 0x51, 0x02], // vibes
+    [0x17, 0xc1, 0x24, 0x07, 0xf8, 0xf8, 0x22, 0x12], // vibraphone
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // tutti
+    [0x01, 0x02, 0xd3, 0x05, 0xc9, 0x95, 0x03, 0x02], // fretless
+    [0x61, 0x63, 0x0c, 0x00, 0x94, 0xc0, 0x33, 0xf6], // synth bass
+    [0x21, 0x72, 0x0d, 0x00, 0xc1, 0xd5, 0x56, 0x06] // sweep
+];
+
+// frequency multiplier, doubled so 0.5 fits in an integer
+const MULTIPLIERS: [f32; 16] = [1.0, 2.0, 4.0, 6.0, 8.0, 10.0, 12.0, 14.0, 16.0, 18.0, 20.0, 20.0, 24.0, 24.0, 30.0, 30.0];
+
+// key scale level attenuation in dB for the top octave, indexed by the top 4 bits of fnum
+const KEY_SCALE_LEVELS: [f32; 16] = [
+    0.0, 9.0, 12.0, 13.875, 15.0, 16.125, 16.875, 17.625,
+    18.0, 18.75, 19.125, 19.5, 19.875, 20.25, 20.625, 21.0
+];
+
+// attenuation at which an operator is considered silent
+const MAX_ATTENUATION: f32 = 48.0;
+
+// time for a decay/release to go through the whole 48 dB at the slowest non-zero rate (1)
+const SLOWEST_DECAY_SECONDS: f32 = 19.64;
+
+// lfo frequencies and depths
+const TREMOLO_HZ: f32 = 3.7;
+const TREMOLO_DB: f32 = 4.8;
+const VIBRATO_HZ: f32 = 6.4;
+const VIBRATO_DEPTH: f32 = 0.004; // +-7 cents
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum EnvelopeState {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+    Off
+}
+
+// settings for one operator, decoded from a patch
+#[derive(Copy, Clone)]
+struct OperatorPatch {
+    tremolo: bool,
+    vibrato: bool,
+    sustained: bool, // false => percussive, the envelope keeps decaying after reaching the sustain level
+    key_scale_rate: bool,
+    multiplier: u8,
+    key_scale_level: u8,
+    rectified: bool, // only the positive half of the sine wave
+    attack_rate: u8,
+    decay_rate: u8,
+    sustain_level: u8,
+    release_rate: u8
+}
+
+struct Patch {
+    modulator: OperatorPatch,
+    carrier: OperatorPatch,
+    total_level: u8, // modulator attenuation
+    feedback: u8
+}
+
+// what both operators of a channel are clocked with for one sample
+struct OperatorClock {
+    key_code: u8, // block and fnum msb, for the key scale rate
+    base_increment: f32, // phase increment in cycles, before the multiplier
+    tremolo: f32, // attenuation in dB
+    vibrato: f32 // frequency factor
+}
+
+struct Operator {
+    phase: f32, // in cycles, 0.0 - 1.0
+    state: EnvelopeState,
+    envelope: f32 // attenuation in dB
+}
+
+struct Channel {
+    fnum: u16, // 9-bit frequency number
+    block: u8, // octave
+    key_on: bool,
+    sustain: bool, // slower release after key off
+    instrument: u8,
+    volume: u8, // carrier attenuation in 3 dB steps
+
+    modulator: Operator,
+    carrier: Operator,
+    feedback: [f32; 2] // the modulator's last two outputs
+}
+
+pub struct Opll {
+    address: u8,
+    user_patch: [u8; 8],
+    channels: [Channel; CHANNELS],
+
+    cycles: u8,
+    tremolo_phase: f32, // in cycles, 0.0 - 1.0
+    vibrato_phase: f32,
+    output: f32
+}
+
+impl Opll {
+    pub fn new() -> Opll {
+        Opll {
+            address: 0,
+            user_patch: [0; 8],
+            channels: [Channel::new(), Channel::new(), Channel::new(), Channel::new(), Channel::new(), Channel::new()],
+
+            cycles: 0,
+            tremolo_phase: 0.0,
+            vibrato_phase: 0.0,
+            output: 0.0
+        }
+    }
+
+    // silence every channel and clear all registers
+    pub fn reset(&mut self) {
+        *self = Opll::new();
+    }
+
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value;
+    }
+
+    pub fn write_data(&mut self, value: u8) {
+        let channel = (self.address & 0x0f) as usize;
+
+        match self.address {
+            0x00..=0x07 => self.user_patch[self.address as usize] = value,
+            0x10..=0x15 => {
+                let c = &mut self.channels[channel];
+                c.fnum = (c.fnum & 0x100) | value as u16;
+            },
+            // --ST OOOF, sustain, key on, octave, high bit of fnum
+            0x20..=0x25 => {
+                let c = &mut self.channels[channel];
+                c.fnum = (c.fnum & 0xff) | (((value & 1) as u16) << 8);
+                c.block = (value >> 1) & 7;
+                c.sustain = value & 0x20 != 0;
+
+                let key_on = value & 0x10 != 0;
+                if key_on && !c.key_on {
+                    c.modulator.key_on();
+                    c.carrier.key_on();
+                } else if !key_on && c.key_on {
+                    c.modulator.key_off();
+                    c.carrier.key_off();
+                }
+                c.key_on = key_on;
+            },
+            // IIII VVVV, instrument and volume
+            0x30..=0x35 => {
+                let c = &mut self.channels[channel];
+                c.instrument = value >> 4;
+                c.volume = value & 0x0f;
+            },
+            _ => ()
+        }
+    }
+
+    // called once per cpu cycle
+    pub fn clock(&mut self) {
+        self.cycles += 1;
+        if self.cycles < CPU_CYCLES_PER_SAMPLE {
+            return;
+        }
+        self.cycles = 0;
+
+        self.tremolo_phase = (self.tremolo_phase + TREMOLO_HZ / SAMPLE_RATE).fract();
+        self.vibrato_phase = (self.vibrato_phase + VIBRATO_HZ / SAMPLE_RATE).fract();
+        let tremolo = TREMOLO_DB * 0.5 * (1.0 - (2.0 * PI * self.tremolo_phase).cos());
+        let vibrato = 1.0 + VIBRATO_DEPTH * (2.0 * PI * self.vibrato_phase).sin();
+
+        let mut sum = 0.0;
+        for i in 0..CHANNELS {
+            let patch = decode_patch(self.patch_data(self.channels[i].instrument));
+            sum += self.channels[i].sample(&patch, tremolo, vibrato);
+        }
+
+        self.output = sum;
+    }
+
+    // a single channel at full volume is about half as loud as a 2A03 pulse
+    pub fn output(&self) -> f32 {
+        self.output * 0.25
+    }
+
+    fn patch_data(&self, instrument: u8) -> [u8; 8] {
+        if instrument == 0 {
+            self.user_patch
+        } else {
+            PATCHES[instrument as usize]
+        }
+    }
+}
+
+fn decode_patch(data: [u8; 8]) -> Patch {
+    let operator = |i: usize, ksl: u8, rectified: bool| OperatorPatch {
+        tremolo: data[i] & 0x80 != 0,
+        vibrato: data[i] & 0x40 != 0,
+        sustained: data[i] & 0x20 != 0,
+        key_scale_rate: data[i] & 0x10 != 0,
+        multiplier: data[i] & 0x0f,
+        key_scale_level: ksl,
+        rectified: rectified,
+        attack_rate: data[4 + i] >> 4,
+        decay_rate: data[4 + i] & 0x0f,
+        sustain_level: data[6 + i] >> 4,
+        release_rate: data[6 + i] & 0x0f
+    };
+
+    // $02: KKTT TTTT, modulator key scale level and total level
+    // $03: KK-Q WFFF, carrier key scale level, carrier/modulator rectified waves, feedback
+    Patch {
+        modulator: operator(0, data[2] >> 6, data[3] & 0x08 != 0),
+        carrier: operator(1, data[3] >> 6, data[3] & 0x10 != 0),
+        total_level: data[2] & 0x3f,
+        feedback: data[3] & 0x07
+    }
+}
+
+impl Channel {
+    fn new() -> Channel {
+        Channel {
+            fnum: 0,
+            block: 0,
+            key_on: false,
+            sustain: false,
+            instrument: 0,
+            volume: 0,
+
+            modulator: Operator::new(),
+            carrier: Operator::new(),
+            feedback: [0.0; 2]
+        }
+    }
+
+    fn sample(&mut self, patch: &Patch, tremolo: f32, vibrato: f32) -> f32 {
+        let clock = OperatorClock {
+            // key scale rate, which speeds up the envelope of higher notes
+            key_code: (self.block << 1) | (self.fnum >> 8) as u8,
+            base_increment: (self.fnum as f32) * (1 << self.block) as f32 / (1 << 19) as f32 / 2.0,
+            tremolo: tremolo,
+            vibrato: vibrato
+        };
+
+        // key scale level attenuation at 6 dB/octave, scaled down by the patch's setting
+        let key_scale = (KEY_SCALE_LEVELS[(self.fnum >> 5) as usize] - 6.0 * (7 - self.block) as f32).max(0.0);
+
+        let sustain = self.sustain;
+        let release = |op: &OperatorPatch| {
+            if sustain {
+                5
+            } else if op.sustained {
+                op.release_rate
+            } else {
+                7
+            }
+        };
+
+        // modulator, with self feedback
+        let m = &patch.modulator;
+        let feedback = if patch.feedback == 0 {
+            0.0
+        } else {
+            (self.feedback[0] + self.feedback[1]) / 2.0 * PI / 16.0 * (1 << (patch.feedback - 1)) as f32
+        };
+        let attenuation = patch.total_level as f32 * 0.75 + key_scale_level(m, key_scale);
+        let modulation = self.modulator.sample(m, &clock, release(m), attenuation, feedback);
+        self.feedback = [self.feedback[1], modulation];
+
+        // carrier, phase modulated by the modulator
+        let c = &patch.carrier;
+        let attenuation = self.volume as f32 * 3.0 + key_scale_level(c, key_scale);
+        self.carrier.sample(c, &clock, release(c), attenuation, modulation * 4.0 * PI)
+    }
+}
+
+fn key_scale_level(op: &OperatorPatch, key_scale: f32) -> f32 {
+    key_scale * [0.0, 0.25, 0.5, 1.0][op.key_scale_level as usize]
+}
+
+impl Operator {
+    fn new() -> Operator {
+        Operator {
+            phase: 0.0,
+            state: EnvelopeState::Off,
+            envelope: MAX_ATTENUATION
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0.0;
+        self.state = EnvelopeState::Attack;
+    }
+
+    fn key_off(&mut self) {
+        if self.state != EnvelopeState::Off {
+            self.state = EnvelopeState::Release;
+        }
+    }
+
+    // advance the phase and envelope by one sample and return the new output
+    //
+    // `modulation` is added to the phase, in radians
+    fn sample(&mut self, op: &OperatorPatch, clock: &OperatorClock, release_rate: u8, attenuation: f32,
+              modulation: f32) -> f32 {
+        let vibrato = if op.vibrato { clock.vibrato } else { 1.0 };
+        self.phase = (self.phase + clock.base_increment * MULTIPLIERS[op.multiplier as usize] * vibrato).fract();
+
+        let key_scale_rate = if op.key_scale_rate { clock.key_code } else { clock.key_code >> 2 };
+        self.update_envelope(op, key_scale_rate, release_rate);
+
+        if self.state == EnvelopeState::Off {
+            return 0.0;
+        }
+
+        let tremolo = if op.tremolo { clock.tremolo } else { 0.0 };
+        let total = self.envelope + attenuation + tremolo;
+
+        let mut wave = (2.0 * PI * self.phase + modulation).sin();
+        if op.rectified && wave < 0.0 {
+            wave = 0.0;
+        }
+
+        wave * 10f32.powf(-total / 20.0)
+    }
+
+    fn update_envelope(&mut self, op: &OperatorPatch, key_scale_rate: u8, release_rate: u8) {
+        let sustain_level = op.sustain_level as f32 * 3.0;
+
+        match self.state {
+            EnvelopeState::Attack => {
+                if op.attack_rate == 15 {
+                    self.envelope = 0.0;
+                } else {
+                    // the attack curve is exponential, fast at first and slowing down near 0 dB
+                    let step = attack_step(op.attack_rate, key_scale_rate);
+                    self.envelope -= (self.envelope + 1.0) * step;
+                }
+
+                if self.envelope <= 0.0 {
+                    self.envelope = 0.0;
+                    self.state = EnvelopeState::Decay;
+                }
+            },
+            EnvelopeState::Decay => {
+                self.envelope += decay_step(op.decay_rate, key_scale_rate);
+                if self.envelope >= sustain_level {
+                    self.envelope = sustain_level;
+                    self.state = EnvelopeState::Sustain;
+                }
+            },
+            EnvelopeState::Sustain => {
+                // percussive patches fade out even while the key is held
+                if !op.sustained {
+                    self.envelope += decay_step(op.release_rate, key_scale_rate);
+                }
+            },
+            EnvelopeState::Release => self.envelope += decay_step(release_rate, key_scale_rate),
+            EnvelopeState::Off => ()
+        }
+
+        if self.envelope >= MAX_ATTENUATION {
+            self.envelope = MAX_ATTENUATION;
+            if self.state != EnvelopeState::Attack {
+                self.state = EnvelopeState::Off;
+            }
+        }
+    }
+}
+
+// the effective rate is 4 * rate + key scale rate, every 4 steps doubles the speed
+fn effective_rate(rate: u8, key_scale_rate: u8) -> f32 {
+    let rate = (rate * 4 + key_scale_rate).min(63);
+    (1 << (rate >> 2)) as f32 * (4 + (rate & 3)) as f32 / 4.0
+}
+
+// dB added per sample while decaying
+fn decay_step(rate: u8, key_scale_rate: u8) -> f32 {
+    if rate == 0 {
+        return 0.0;
+    }
+
+    MAX_ATTENUATION / (SLOWEST_DECAY_SECONDS * SAMPLE_RATE) * effective_rate(rate, key_scale_rate) / 2.0
+}
+
+// fraction of the remaining attenuation removed per sample while attacking, attacks are roughly
+// 14 times faster than decays
+fn attack_step(rate: u8, key_scale_rate: u8) -> f32 {
+    if rate == 0 {
+        return 0.0;
+    }
+
+    let seconds = SLOWEST_DECAY_SECONDS / 14.0 * 2.0 / effective_rate(rate, key_scale_rate);
+    ((MAX_ATTENUATION + 1.0).ln() / (seconds * SAMPLE_RATE)).min(1.0)
+}
@@ -0,0 +1,219 @@
+// Sunsoft 5B expansion audio, a Yamaha YM2149F (an AY-3-8910 clone) built into the FME-7
+//
+// https://wiki.nesdev.com/w/index.php/Sunsoft_5B_audio
+//
+// three square wave tone channels which can each be mixed with a shared noise generator, and a
+// shared envelope generator that can drive any channel's volume instead of its fixed volume
+pub struct Sunsoft5bAudio {
+    address: u8,
+
+    tones: [Tone; 3],
+
+    noise_period: u8,
+    noise_divider: u16,
+    noise_lfsr: u32, // 17-bit
+    noise_output: bool,
+
+    envelope_period: u16,
+    envelope_divider: u32,
+    envelope_step: u8, // 0-31
+    envelope_attack: bool, // counting up instead of down
+    envelope_continue: bool,
+    envelope_alternate: bool,
+    envelope_hold: bool,
+    envelope_holding: bool
+}
+
+struct Tone {
+    period: u16, // 12-bit
+    divider: u32,
+    output: bool,
+
+    tone_disabled: bool,
+    noise_disabled: bool,
+    volume: u8,
+    use_envelope: bool
+}
+
+// the chip is clocked at half the cpu rate and a tone flips every 8 of its clocks per unit of period,
+// so a tone's frequency is cpu clock / (32 * period)
+const CPU_CYCLES_PER_PERIOD: u32 = 16;
+
+impl Sunsoft5bAudio {
+    pub fn new() -> Sunsoft5bAudio {
+        Sunsoft5bAudio {
+            address: 0,
+
+            tones: [Tone::new(), Tone::new(), Tone::new()],
+
+            noise_period: 0,
+            noise_divider: 0,
+            noise_lfsr: 1,
+            noise_output: false,
+
+            envelope_period: 0,
+            envelope_divider: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_continue: false,
+            envelope_alternate: false,
+            envelope_hold: false,
+            envelope_holding: true
+        }
+    }
+
+    // $C000-$DFFF
+    pub fn write_address(&mut self, value: u8) {
+        self.address = value & 0x0f;
+    }
+
+    // $E000-$FFFF
+    pub fn write_data(&mut self, value: u8) {
+        match self.address {
+            0x00 | 0x02 | 0x04 => {
+                let tone = &mut self.tones[self.address as usize / 2];
+                tone.period = (tone.period & 0x0f00) | value as u16;
+            },
+            0x01 | 0x03 | 0x05 => {
+                let tone = &mut self.tones[self.address as usize / 2];
+                tone.period = (tone.period & 0x00ff) | (((value & 0x0f) as u16) << 8);
+            },
+            0x06 => self.noise_period = value & 0x1f,
+            // --CB Acba, noise and tone disable for channels C, B and A
+            0x07 => {
+                for (i, tone) in self.tones.iter_mut().enumerate() {
+                    tone.tone_disabled = value & (1 << i) != 0;
+                    tone.noise_disabled = value & (8 << i) != 0;
+                }
+            },
+            // ---E VVVV, use the envelope or a fixed volume
+            0x08..=0x0a => {
+                let tone = &mut self.tones[self.address as usize - 8];
+                tone.volume = value & 0x0f;
+                tone.use_envelope = value & 0x10 != 0;
+            },
+            0x0b => self.envelope_period = (self.envelope_period & 0xff00) | value as u16,
+            0x0c => self.envelope_period = (self.envelope_period & 0x00ff) | ((value as u16) << 8),
+            // ---- CAaH, continue, attack, alternate, hold. writing restarts the envelope
+            0x0d => {
+                self.envelope_continue = value & 8 != 0;
+                self.envelope_attack = value & 4 != 0;
+                self.envelope_alternate = value & 2 != 0;
+                self.envelope_hold = value & 1 != 0;
+                self.envelope_step = 0;
+                self.envelope_divider = 0;
+                self.envelope_holding = false;
+            },
+            _ => ()
+        }
+    }
+
+    // called once per cpu cycle
+    pub fn clock(&mut self) {
+        for tone in self.tones.iter_mut() {
+            tone.clock();
+        }
+
+        // the noise divider runs at half the rate of the tone dividers
+        if self.noise_divider == 0 {
+            self.noise_divider = (self.noise_period.max(1) as u16) * CPU_CYCLES_PER_PERIOD as u16 * 2;
+
+            // 17-bit lfsr with taps at bits 0 and 3
+            let feedback = (self.noise_lfsr ^ (self.noise_lfsr >> 3)) & 1;
+            self.noise_lfsr = (self.noise_lfsr >> 1) | (feedback << 16);
+            self.noise_output = self.noise_lfsr & 1 != 0;
+        }
+        self.noise_divider -= 1;
+
+        self.clock_envelope();
+    }
+
+    fn clock_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+
+        self.envelope_divider += 1;
+        if self.envelope_divider < self.envelope_period.max(1) as u32 * CPU_CYCLES_PER_PERIOD {
+            return;
+        }
+        self.envelope_divider = 0;
+
+        if self.envelope_step < 31 {
+            self.envelope_step += 1;
+            return;
+        }
+
+        // the end of a ramp
+        if !self.envelope_continue {
+            // drop to 0 and stay there
+            self.envelope_attack = false;
+            self.envelope_step = 31;
+            self.envelope_holding = true;
+        } else if self.envelope_hold {
+            // stay at the final level, or the opposite one when alternating
+            if self.envelope_alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_holding = true;
+        } else {
+            if self.envelope_alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+
+    // 0-31, the level the envelope generator is currently outputting
+    fn envelope_level(&self) -> u8 {
+        if self.envelope_attack {
+            self.envelope_step
+        } else {
+            31 - self.envelope_step
+        }
+    }
+
+    pub fn output(&self) -> f32 {
+        let envelope = self.envelope_level();
+
+        let sum: f32 = self.tones.iter().map(|tone| {
+            let high = (tone.output || tone.tone_disabled) && (self.noise_output || tone.noise_disabled);
+            if !high {
+                return 0.0;
+            }
+
+            // the volume is logarithmic, 3 dB per fixed volume step and 1.5 dB per envelope step
+            let level = if tone.use_envelope { envelope } else { tone.volume * 2 + 1 };
+            if level <= 1 {
+                0.0
+            } else {
+                10f32.powf((level as f32 - 31.0) * 1.5 / 20.0)
+            }
+        }).sum();
+
+        sum * 0.3
+    }
+}
+
+impl Tone {
+    fn new() -> Tone {
+        Tone {
+            period: 0,
+            divider: 0,
+            output: false,
+
+            tone_disabled: false,
+            noise_disabled: false,
+            volume: 0,
+            use_envelope: false
+        }
+    }
+
+    fn clock(&mut self) {
+        self.divider += 1;
+        if self.divider >= self.period.max(1) as u32 * CPU_CYCLES_PER_PERIOD {
+            self.divider = 0;
+            self.output = !self.output;
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use crate::cartridge::{Cartridge, NesHeader, Mirror};
+use crate::region::Region;
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+
+// UNIF, the chunked format some multicart and pirate dumps only exist in
+//
+// https://wiki.nesdev.com/w/index.php/UNIF
+//
+// a 32 byte header ("UNIF", the revision and padding) is followed by chunks of a 4 byte id, a
+// 32-bit little endian length and the data. the board is given by name instead of a mapper number,
+// so it's looked up in a table of the boards znes implements
+pub fn parse(buffer: &[u8], file_path: String) -> Cartridge {
+    let mut board = None;
+    let mut prg_chunks: Vec<Option<&[u8]>> = vec![None; 16];
+    let mut chr_chunks: Vec<Option<&[u8]>> = vec![None; 16];
+    let mut mirror = Mirror::Horizontal;
+    let mut battery = false;
+
+    let mut i = 0x20;
+    while i + 8 <= buffer.len() {
+        let id = &buffer[i..i + 4];
+        let length = u32::from_le_bytes([buffer[i + 4], buffer[i + 5], buffer[i + 6], buffer[i + 7]]) as usize;
+        let data = match buffer.get(i + 8..i + 8 + length) {
+            Some(data) => data,
+            None => panic!("Truncated UNIF chunk! {}", String::from_utf8_lossy(id))
+        };
+
+        match id {
+            // null terminated board name
+            b"MAPR" => {
+                let name = data.split(|&b| b == 0).next().unwrap_or(&[]);
+                board = Some(String::from_utf8_lossy(name).into_owned());
+            },
+            // PRG0-PRGF and CHR0-CHRF are concatenated in order
+            [b'P', b'R', b'G', n] => {
+                if let Some(n) = (*n as char).to_digit(16) {
+                    prg_chunks[n as usize] = Some(data);
+                }
+            },
+            [b'C', b'H', b'R', n] => {
+                if let Some(n) = (*n as char).to_digit(16) {
+                    chr_chunks[n as usize] = Some(data);
+                }
+            },
+            // 0: horizontal, 1: vertical, 2/3: single screen, 4: four screen, 5: mapper controlled
+            b"MIRR" => match data.first() {
+                Some(0) => mirror = Mirror::Horizontal,
+                Some(1) => mirror = Mirror::Vertical,
+                Some(2) => mirror = Mirror::Single0,
+                Some(3) => mirror = Mirror::Single1,
+                Some(4) => println!("Four-screen mirroring isn't supported, using horizontal mirroring"),
+                // the mapper switches it itself, the header's mirroring is never used
+                Some(5) => (),
+                _ => println!("Unknown UNIF mirroring! {:?}", data.first())
+            },
+            b"BATR" => battery = true,
+            _ => ()
+        }
+
+        i += 8 + length;
+    }
+
+    let board = match board {
+        Some(board) => board,
+        None => panic!("UNIF file has no board name!")
+    };
+    let mapper = match board_mapper(&board) {
+        Some(mapper) => mapper,
+        None => panic!("Unimplemented UNIF board! {}", board)
+    };
+
+    let prg = concat_chunks(&prg_chunks, 0x4000);
+    let chr = concat_chunks(&chr_chunks, 0x2000);
+
+    let header = NesHeader {
+        file_path: file_path,
+        submapper: 0,
+        prg_rom_size: prg.len() / 0x4000,
+        chr_rom_size: chr.len() / 0x2000,
+        mirror: mirror,
+        battery_backed_ram: battery,
+        prg_ram_size: None,
+        region: Region::NTSC,
+        trainer: false,
+        ignore_mirror: false,
+        nes2: false
+    };
+
+    println!("UNIF board {} (mapper {})", board, mapper);
+    Cartridge {
+        header: header,
+        prg: prg,
+        chr: chr,
+        trainer: Vec::new(),
+        disk: Vec::new(),
+        mapper: mapper,
+        game_dir: PathBuf::new(),
+        saved_battery: RefCell::new(Vec::new())
+    }
+}
+
+// chunks smaller than the mappers' bank size (like an 8 KiB NROM prg) are mirrored to fill it
+fn concat_chunks(chunks: &[Option<&[u8]>], unit: usize) -> Vec<u8> {
+    let data: Vec<u8> = chunks.iter().flatten().flat_map(|chunk| chunk.iter().copied()).collect();
+    if data.is_empty() {
+        return data;
+    }
+
+    let size = data.len().div_ceil(unit) * unit;
+    data.iter().copied().cycle().take(size).collect()
+}
+
+// https://wiki.nesdev.com/w/index.php/UNIF_to_NES_2.0_Mapping
+//
+// names are usually prefixed by who made the board, e.g. NES-SNROM or UNL-..., which doesn't
+// change the wiring
+fn board_mapper(board: &str) -> Option<u16> {
+    let name = match board.find('-') {
+        Some(i) if ["NES", "HVC", "UNL", "BTL", "BMC", "IREM", "KONAMI"].contains(&&board[..i]) => &board[i + 1..],
+        _ => board
+    };
+
+    let mapper = match name {
+        "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" => 0,
+        "SAROM" | "SBROM" | "SCROM" | "SEROM" | "SFROM" | "SGROM" | "SHROM" | "SJROM" | "SKROM"
+        | "SLROM" | "SL1ROM" | "SNROM" | "SOROM" | "SUROM" | "SXROM" => 1,
+        "UNROM" | "UOROM" => 2,
+        "CNROM" => 3,
+        "EKROM" | "ELROM" | "ETROM" | "EWROM" => 5,
+        "GNROM" | "MHROM" => 66,
+        "BTR" | "JLROM" | "JSROM" => 69,
+        _ => return None
+    };
+
+    Some(mapper)
+}
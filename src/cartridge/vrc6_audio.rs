@@ -0,0 +1,201 @@
+// VRC6 expansion audio, two pulse channels and a sawtooth
+//
+// https://wiki.nesdev.com/w/index.php/VRC6_audio
+//
+// every channel is clocked by the cpu through a 12-bit period divider. the frequency control
+// register can halt all three dividers or shift their periods right by 4 or 8 bits
+pub struct Vrc6Audio {
+    pulse: [Pulse; 2],
+    saw: Sawtooth,
+
+    halt: bool,
+    period_shift: u8
+}
+
+struct Pulse {
+    enabled: bool,
+    constant: bool, // ignore the duty cycle and output the volume all the time
+    duty: u8,
+    volume: u8,
+
+    period: u16,
+    divider: u16,
+    step: u8 // counts down from 15, the output is high while step <= duty
+}
+
+struct Sawtooth {
+    enabled: bool,
+    rate: u8, // added to the accumulator every other divider clock
+
+    period: u16,
+    divider: u16,
+    step: u8, // 14 steps, the accumulator resets after the last
+    accumulator: u8
+}
+
+impl Vrc6Audio {
+    pub fn new() -> Vrc6Audio {
+        Vrc6Audio {
+            pulse: [Pulse::new(), Pulse::new()],
+            saw: Sawtooth::new(),
+
+            halt: false,
+            period_shift: 0
+        }
+    }
+
+    // registers are given as $9000-$9003, $A000-$A002 and $B000-$B002 with the board's address
+    // line swapping already undone
+    pub fn write(&mut self, address: usize, value: u8) {
+        match address {
+            0x9000..=0x9002 => self.pulse[0].write(address & 3, value),
+            // ---- -ABH
+            //       ||+- halt all channels
+            //       |+-- 16x frequency (period >> 4)
+            //       +--- 256x frequency (period >> 8), takes precedence
+            0x9003 => {
+                self.halt = value & 1 != 0;
+                self.period_shift = if value & 4 != 0 { 8 } else if value & 2 != 0 { 4 } else { 0 };
+            },
+            0xa000..=0xa002 => self.pulse[1].write(address & 3, value),
+            0xb000..=0xb002 => self.saw.write(address & 3, value),
+            _ => ()
+        }
+    }
+
+    // called once per cpu cycle
+    pub fn clock(&mut self) {
+        if self.halt {
+            return;
+        }
+
+        let shift = self.period_shift;
+        self.pulse[0].clock(shift);
+        self.pulse[1].clock(shift);
+        self.saw.clock(shift);
+    }
+
+    // the pulses are 4 bits and the sawtooth 5 bits, their sum is normalized so that a full
+    // volume pulse is about as loud as one of the 2A03's pulses
+    pub fn output(&self) -> f32 {
+        let sum = self.pulse[0].output() + self.pulse[1].output() + self.saw.output();
+        sum as f32 / 61.0 * 0.5
+    }
+}
+
+impl Pulse {
+    fn new() -> Pulse {
+        Pulse {
+            enabled: false,
+            constant: false,
+            duty: 0,
+            volume: 0,
+
+            period: 0,
+            divider: 0,
+            step: 15
+        }
+    }
+
+    fn write(&mut self, register: usize, value: u8) {
+        match register {
+            // MDDD VVVV, mode, duty cycle, volume
+            0 => {
+                self.constant = value & 0x80 != 0;
+                self.duty = (value >> 4) & 7;
+                self.volume = value & 0x0f;
+            },
+            1 => self.period = (self.period & 0x0f00) | value as u16,
+            // E--- FFFF, enable and the high bits of the period
+            2 => {
+                self.period = (self.period & 0x00ff) | (((value & 0x0f) as u16) << 8);
+                self.enabled = value & 0x80 != 0;
+
+                // disabling resets the duty cycle
+                if !self.enabled {
+                    self.step = 15;
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.divider == 0 {
+            self.divider = self.period >> shift;
+            self.step = if self.step == 0 { 15 } else { self.step - 1 };
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+}
+
+impl Sawtooth {
+    fn new() -> Sawtooth {
+        Sawtooth {
+            enabled: false,
+            rate: 0,
+
+            period: 0,
+            divider: 0,
+            step: 0,
+            accumulator: 0
+        }
+    }
+
+    fn write(&mut self, register: usize, value: u8) {
+        match register {
+            // --AA AAAA, accumulator rate
+            0 => self.rate = value & 0x3f,
+            1 => self.period = (self.period & 0x0f00) | value as u16,
+            // E--- FFFF, enable and the high bits of the period
+            2 => {
+                self.period = (self.period & 0x00ff) | (((value & 0x0f) as u16) << 8);
+                self.enabled = value & 0x80 != 0;
+
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.divider == 0 {
+            self.divider = self.period >> shift;
+
+            self.step += 1;
+            if self.step == 14 {
+                self.step = 0;
+                self.accumulator = 0;
+            } else if self.step & 1 == 0 {
+                self.accumulator = self.accumulator.wrapping_add(self.rate);
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    // the top 5 bits of the 8-bit accumulator
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
@@ -0,0 +1,96 @@
+// the irq counter shared by Konami's VRC4, VRC6 and VRC7
+//
+// https://wiki.nesdev.com/w/index.php/VRC_IRQ
+//
+// the counter is always clocked by the cpu. in scanline mode a prescaler divides the cpu clock by
+// 113.667 (341 / 3) so the counter ticks roughly once per scanline, in cycle mode it ticks every
+// cpu cycle. either way the irq fires when the 8-bit counter overflows, reloading it from the latch
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool, // false => scanline mode, true => cpu cycle mode
+
+    pub pending: bool
+}
+
+impl VrcIrq {
+    pub fn new() -> VrcIrq {
+        VrcIrq {
+            latch: 0,
+            counter: 0,
+            prescaler: 341,
+
+            enabled: false,
+            enable_after_ack: false,
+            cycle_mode: false,
+
+            pending: false
+        }
+    }
+
+    // VRC4 splits the latch across two registers
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xf0) | (value & 0x0f);
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0x0f) | ((value & 0x0f) << 4);
+    }
+
+    // VRC6 and VRC7 write the whole latch at once
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    // ---- -MEA
+    //       ||+- enable after acknowledgement
+    //       |+-- enable
+    //       +--- mode (0 => scanline, 1 => cpu cycle)
+    pub fn write_control(&mut self, value: u8) {
+        self.enable_after_ack = value & 1 != 0;
+        self.enabled = value & 2 != 0;
+        self.cycle_mode = value & 4 != 0;
+
+        if self.enabled {
+            self.counter = self.latch;
+            self.prescaler = 341;
+        }
+
+        self.pending = false;
+    }
+
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.enabled = self.enable_after_ack;
+    }
+
+    // called once per cpu cycle
+    pub fn clock(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        if self.cycle_mode {
+            self.clock_counter();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += 341;
+                self.clock_counter();
+            }
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+// code/data logger, writes FCEUX compatible .cdl files
+//
+// http://fceux.com/web/help/CodeDataLogger.html
+//
+// the file is a byte per byte of prg rom, followed by a byte per byte of chr rom
+//
+// prg bytes: xPdcAADC
+//     C  - accessed as code
+//     D  - accessed as data
+//     AA - which 8 KiB cpu window the byte was mapped into when it was last accessed
+//          (00 => $8000, 01 => $A000, 10 => $C000, 11 => $E000)
+//     c  - indirectly accessed as code, e.g. the destination of a JMP ($nnnn)
+//     d  - indirectly accessed as data, e.g. the target of a LDA ($nn),Y
+//     P  - pcm audio data (not logged, the APU isn't implemented)
+//
+// chr bytes: xxxxxxRD
+//     D - drawn by the ppu
+//     R - read by the cpu through $2007
+pub const PRG_CODE: u8 = 1;
+pub const PRG_DATA: u8 = 1 << 1;
+pub const PRG_INDIRECT_CODE: u8 = 1 << 4;
+pub const PRG_INDIRECT_DATA: u8 = 1 << 5;
+
+pub const CHR_RENDERED: u8 = 1;
+pub const CHR_READ: u8 = 1 << 1;
+
+pub struct CodeDataLogger {
+    file_path: String,
+    prg: Vec<u8>,
+    chr: Vec<u8>
+}
+
+impl CodeDataLogger {
+    // an existing log is extended rather than overwritten, so coverage can be built up over
+    // multiple sessions
+    pub fn new(file_path: String, prg_size: usize, chr_size: usize) -> CodeDataLogger {
+        let mut logger = CodeDataLogger {
+            file_path: file_path,
+            prg: vec![0; prg_size],
+            chr: vec![0; chr_size]
+        };
+
+        if Path::new(&logger.file_path).exists() {
+            let mut file = File::open(&logger.file_path).expect("Failed to open .cdl file");
+            let mut buffer = vec![];
+            file.read_to_end(&mut buffer).expect("Failed to read .cdl file");
+
+            if buffer.len() == prg_size + chr_size {
+                logger.prg.copy_from_slice(&buffer[..prg_size]);
+                logger.chr.copy_from_slice(&buffer[prg_size..]);
+                println!("Loaded code/data log from: {}", logger.file_path);
+            } else {
+                println!("Ignoring code/data log with mismatched size: {}", logger.file_path);
+            }
+        }
+
+        logger
+    }
+
+    pub fn log_prg(&mut self, offset: usize, address: usize, flags: u8) {
+        if let Some(byte) = self.prg.get_mut(offset) {
+            let window = (((address >> 13) & 3) as u8) << 2;
+            *byte = (*byte & !0x0c) | window | flags;
+        }
+    }
+
+    pub fn log_chr(&mut self, offset: usize, flags: u8) {
+        if let Some(byte) = self.chr.get_mut(offset) {
+            *byte |= flags;
+        }
+    }
+
+    pub fn save(&self) {
+        let mut file = File::create(&self.file_path).expect("Failed to create .cdl file");
+        file.write_all(&self.prg).expect("Failed to write to .cdl file");
+        file.write_all(&self.chr).expect("Failed to write to .cdl file");
+
+        println!("Wrote code/data log to: {}", self.file_path);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::cpu::Mode;
+use crate::cpu::MODE_TABLE;
+use crate::cpu::debug::OPCODE_DISPLAY_NAMES;
+
+use std::fmt;
+
+// a single decoded instruction
+//
+// the operand bytes are kept as they appear in memory (little endian), formatting them into
+// proper 6502 syntax is left to the Display implementation
+#[derive(Clone, Debug)]
+pub struct Instruction {
+    pub address: u16,
+    pub opcode: u8,
+    pub operands: Vec<u8>,
+    pub mode: Mode
+}
+
+impl Instruction {
+    // total number of bytes the instruction occupies, including the opcode
+    pub fn len(&self) -> usize {
+        1 + self.operands.len()
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.opcode];
+        bytes.extend_from_slice(&self.operands);
+        bytes
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        OPCODE_DISPLAY_NAMES[self.opcode as usize]
+    }
+
+    // the address the operand refers to, if it refers to one directly
+    //
+    // for relative branches this is the branch destination, not the raw offset
+    pub fn target(&self) -> Option<u16> {
+        match self.mode {
+            Mode::ABS | Mode::ABX | Mode::ABY | Mode::IND => Some(self.operand_u16()),
+            Mode::ZPG | Mode::ZPX | Mode::ZPY | Mode::IDX | Mode::INX => Some(self.operands[0] as u16),
+            Mode::REL => {
+                let offset = self.operands[0] as i8;
+                Some(self.address.wrapping_add(2).wrapping_add(offset as u16))
+            },
+            Mode::ACC | Mode::IMM | Mode::IMP => None
+        }
+    }
+
+    // the operand text with the target address replaced by the given label, e.g. "(ptr),Y"
+    pub fn format_operand(&self, label: Option<&str>) -> String {
+        let target = match (label, self.target()) {
+            (Some(label), _) => String::from(label),
+            (None, Some(address)) => match self.mode {
+                Mode::ZPG | Mode::ZPX | Mode::ZPY | Mode::IDX | Mode::INX => format!("${:02X}", address),
+                _ => format!("${:04X}", address)
+            },
+            (None, None) => String::new()
+        };
+
+        match self.mode {
+            Mode::ABS | Mode::ZPG | Mode::REL => target,
+            Mode::ABX | Mode::ZPX => format!("{},X", target),
+            Mode::ABY | Mode::ZPY => format!("{},Y", target),
+            Mode::ACC => String::from("A"),
+            Mode::IMM => format!("#${:02X}", self.operands[0]),
+            Mode::IMP => String::new(),
+            Mode::IDX => format!("({},X)", target),
+            Mode::IND => format!("({})", target),
+            Mode::INX => format!("({}),Y", target)
+        }
+    }
+
+    fn operand_u16(&self) -> u16 {
+        (self.operands[1] as u16) << 8 | self.operands[0] as u16
+    }
+
+    // the full instruction text with the operand's target replaced by a label, e.g. "LDA (ptr),Y"
+    pub fn format_with_label(&self, label: Option<&str>) -> String {
+        let operand = self.format_operand(label);
+        if operand.is_empty() {
+            String::from(self.mnemonic())
+        } else {
+            format!("{} {}", self.mnemonic(), operand)
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format_with_label(None))
+    }
+}
+
+// number of operand bytes that follow the opcode for each addressing mode
+pub fn operand_size(mode: Mode) -> usize {
+    match mode {
+        Mode::ACC | Mode::IMP => 0,
+        Mode::IMM | Mode::IDX | Mode::INX | Mode::REL | Mode::ZPG | Mode::ZPX | Mode::ZPY => 1,
+        Mode::ABS | Mode::ABX | Mode::ABY | Mode::IND => 2
+    }
+}
+
+// decode the instruction at the given address
+//
+// memory is accessed through the closure so the caller decides how addresses are resolved, be it
+// through the mapper's current bank layout or straight out of a single prg bank
+pub fn disassemble<F: Fn(u16) -> u8>(read: F, address: u16) -> Instruction {
+    let opcode = read(address);
+    let mode = MODE_TABLE[opcode as usize];
+
+    let operands = (0..operand_size(mode))
+        .map(|i| read(address.wrapping_add(1 + i as u16)))
+        .collect();
+
+    Instruction {
+        address: address,
+        opcode: opcode,
+        operands: operands,
+        mode: mode
+    }
+}
+
+// decode `count` consecutive instructions starting at the given address
+pub fn disassemble_range<F: Fn(u16) -> u8>(read: F, address: u16, count: usize) -> Vec<Instruction> {
+    let mut instructions = Vec::with_capacity(count);
+    let mut address = address;
+
+    for _ in 0..count {
+        let instruction = disassemble(&read, address);
+        address = address.wrapping_add(instruction.len() as u16);
+        instructions.push(instruction);
+    }
+
+    instructions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // disassemble from a small program loaded at $8000
+    fn program(bytes: &[u8], count: usize) -> Vec<Instruction> {
+        disassemble_range(|a| bytes.get(a.wrapping_sub(0x8000) as usize).copied().unwrap_or(0), 0x8000, count)
+    }
+
+    #[test]
+    fn decodes_every_addressing_mode() {
+        let bytes = [
+            0xa9, 0x10, // LDA #$10
+            0xad, 0x34, 0x12, // LDA $1234
+            0xbd, 0x34, 0x12, // LDA $1234,X
+            0xb9, 0x34, 0x12, // LDA $1234,Y
+            0xa5, 0x20, // LDA $20
+            0xb5, 0x20, // LDA $20,X
+            0xb6, 0x20, // LDX $20,Y
+            0xa1, 0x20, // LDA ($20,X)
+            0xb1, 0x20, // LDA ($20),Y
+            0x6c, 0xfc, 0xff, // JMP ($FFFC)
+            0x0a, // ASL A
+            0xe8 // INX
+        ];
+        let text: Vec<String> = program(&bytes, 12).iter().map(|i| i.to_string()).collect();
+
+        assert_eq!(text, [
+            "LDA #$10", "LDA $1234", "LDA $1234,X", "LDA $1234,Y", "LDA $20", "LDA $20,X",
+            "LDX $20,Y", "LDA ($20,X)", "LDA ($20),Y", "JMP ($FFFC)", "ASL A", "INX"
+        ]);
+    }
+
+    #[test]
+    fn consecutive_instructions_follow_their_lengths() {
+        let instructions = program(&[0xa9, 0x01, 0x8d, 0x00, 0x20, 0xea], 3);
+
+        let addresses: Vec<u16> = instructions.iter().map(|i| i.address).collect();
+        assert_eq!(addresses, [0x8000, 0x8002, 0x8005]);
+        assert_eq!(instructions[1].bytes(), [0x8d, 0x00, 0x20]);
+        assert_eq!(instructions[1].len(), 3);
+    }
+
+    #[test]
+    fn branch_targets_are_relative_to_the_next_instruction() {
+        let forward = program(&[0xd0, 0x05], 1);
+        assert_eq!(forward[0].target(), Some(0x8007));
+        assert_eq!(forward[0].to_string(), "BNE $8007");
+
+        let backward = program(&[0xd0, 0xfe], 1);
+        assert_eq!(backward[0].target(), Some(0x8000));
+    }
+
+    #[test]
+    fn labels_replace_the_target_address() {
+        let instructions = program(&[0xb1, 0x20, 0xa9, 0x10], 2);
+
+        assert_eq!(instructions[0].format_with_label(Some("ptr")), "LDA (ptr),Y");
+        // immediate operands aren't addresses
+        assert_eq!(instructions[1].target(), None);
+        assert_eq!(instructions[1].format_with_label(None), "LDA #$10");
+    }
+}
@@ -2,11 +2,17 @@ mod opcodes;
 mod status;
 mod unofficial_opcodes;
 mod debug;
+pub mod disasm;
+pub mod trace;
 
 use crate::cpu::status::Status;
 use crate::cartridge::Mapper;
 use crate::ppu::PPU;
 use crate::controller::Controller;
+use crate::cpu::trace::Tracer;
+use crate::cdl;
+use crate::cdl::CodeDataLogger;
+use crate::symbols::{Location, SymbolTable};
 
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -28,6 +34,42 @@ pub enum Mode {
     ZPY // ZeroPageY
 }
 
+// addressing mode of every opcode, shared by the cpu and the disassembler
+pub const MODE_TABLE: [Mode; 256] = [
+    Mode::IMP, Mode::IDX, Mode::IMP, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
+    Mode::IMP, Mode::IMM, Mode::ACC, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
+    Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
+    Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX,
+    Mode::ABS, Mode::IDX, Mode::IMP, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
+    Mode::IMP, Mode::IMM, Mode::ACC, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
+    Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
+    Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX,
+    Mode::IMP, Mode::IDX, Mode::IMP, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
+    Mode::IMP, Mode::IMM, Mode::ACC, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
+    Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
+    Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX,
+    Mode::IMP, Mode::IDX, Mode::IMP, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
+    Mode::IMP, Mode::IMM, Mode::ACC, Mode::IMM, Mode::IND, Mode::ABS, Mode::ABS, Mode::ABS,
+    Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
+    Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX,
+    Mode::IMM, Mode::IDX, Mode::IMM, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
+    Mode::IMP, Mode::IMM, Mode::IMP, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
+    Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPY, Mode::ZPY,
+    Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABY, Mode::ABY,
+    Mode::IMM, Mode::IDX, Mode::IMM, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
+    Mode::IMP, Mode::IMM, Mode::IMP, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
+    Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPY, Mode::ZPY,
+    Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABY, Mode::ABY,
+    Mode::IMM, Mode::IDX, Mode::IMM, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
+    Mode::IMP, Mode::IMM, Mode::IMP, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
+    Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
+    Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX,
+    Mode::IMM, Mode::IDX, Mode::IMM, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
+    Mode::IMP, Mode::IMM, Mode::IMP, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
+    Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
+    Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX
+];
+
 pub struct StepInfo {
     address: usize,
     mode: Mode
@@ -51,6 +93,14 @@ pub struct CPU {
 
     pub controllers: [Controller; 2], // controllers[0] is controller 1, controllers[1] is controller 2
 
+    pub tracer: Option<Tracer>,
+    pub symbols: SymbolTable,
+    pub breakpoints: Vec<Location>,
+
+    pub cdl: Option<Rc<RefCell<CodeDataLogger>>>,
+    cdl_access: u8, // how the current mapper reads should be logged
+    indirect_jump: bool, // the last instruction was a JMP ($nnnn)
+
     opcode_table: [fn(&mut Self, StepInfo); 256],
     mode_table: [Mode; 256],
     cycle_table: [u8; 256],
@@ -78,6 +128,14 @@ impl CPU {
 
             controllers: [Controller::new(); 2],
 
+            tracer: None,
+            symbols: SymbolTable::new(),
+            breakpoints: Vec::new(),
+
+            cdl: None,
+            cdl_access: cdl::PRG_CODE,
+            indirect_jump: false,
+
             opcode_table: [
                 CPU::brk, CPU::ora, CPU::stp, CPU::slo, CPU::nop, CPU::ora, CPU::asl, CPU::slo,
                 CPU::php, CPU::ora, CPU::asl, CPU::anc, CPU::nop, CPU::ora, CPU::asl, CPU::slo,
@@ -113,40 +171,7 @@ impl CPU {
                 CPU::sed, CPU::sbc, CPU::nop, CPU::isc, CPU::nop, CPU::sbc, CPU::inc, CPU::isc
             ],
 
-            mode_table: [
-                Mode::IMP, Mode::IDX, Mode::IMP, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
-                Mode::IMP, Mode::IMM, Mode::ACC, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
-                Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
-                Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX,
-                Mode::ABS, Mode::IDX, Mode::IMP, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
-                Mode::IMP, Mode::IMM, Mode::ACC, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
-                Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
-                Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX,
-                Mode::IMP, Mode::IDX, Mode::IMP, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
-                Mode::IMP, Mode::IMM, Mode::ACC, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
-                Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
-                Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX,
-                Mode::IMP, Mode::IDX, Mode::IMP, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
-                Mode::IMP, Mode::IMM, Mode::ACC, Mode::IMM, Mode::IND, Mode::ABS, Mode::ABS, Mode::ABS,
-                Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
-                Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX,
-                Mode::IMM, Mode::IDX, Mode::IMM, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
-                Mode::IMP, Mode::IMM, Mode::IMP, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
-                Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPY, Mode::ZPY,
-                Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABY, Mode::ABY,
-                Mode::IMM, Mode::IDX, Mode::IMM, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
-                Mode::IMP, Mode::IMM, Mode::IMP, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
-                Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPY, Mode::ZPY,
-                Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABY, Mode::ABY,
-                Mode::IMM, Mode::IDX, Mode::IMM, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
-                Mode::IMP, Mode::IMM, Mode::IMP, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
-                Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
-                Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX,
-                Mode::IMM, Mode::IDX, Mode::IMM, Mode::IDX, Mode::ZPG, Mode::ZPG, Mode::ZPG, Mode::ZPG,
-                Mode::IMP, Mode::IMM, Mode::IMP, Mode::IMM, Mode::ABS, Mode::ABS, Mode::ABS, Mode::ABS,
-                Mode::REL, Mode::INX, Mode::IMP, Mode::INX, Mode::ZPX, Mode::ZPX, Mode::ZPX, Mode::ZPX,
-                Mode::IMP, Mode::ABY, Mode::IMP, Mode::ABY, Mode::ABX, Mode::ABX, Mode::ABX, Mode::ABX
-            ],
+            mode_table: MODE_TABLE,
 
             cycle_table: [
                 7, 6, 2, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6,
@@ -218,10 +243,6 @@ impl CPU {
     }
 
     pub fn step(&mut self) -> u64 {
-        // debug info
-        // let op = self.read(self.pc as usize);
-        // println!("{:X}  {} {}    A:{:X} X:{:X} Y:{:X} P:{:X} SP:{:X} CYC:{}", self.pc, op, debug::_OPCODE_DISPLAY_NAMES[op as usize], self.a, self.x, self.y, u8::from(self.p), self.sp, self.cycles);
-
         // the OAM DMA steals cycles from the CPU when it is ran
         // thus the cpu stalls until the dma transfer is finished
         if self.dma_delay > 0 {
@@ -235,6 +256,11 @@ impl CPU {
             self.ppu.trigger_nmi = false;
         }
 
+        // the mapper's irq line is level triggered, it stays asserted until the game acknowledges it
+        if !self.p.interrupt && self.mapper.borrow().irq_pending() {
+            self.irq();
+        }
+
         let cycles = self.cycles;
 
         /*
@@ -248,6 +274,13 @@ impl CPU {
         self.interrupt = None;
         */
 
+        if self.tracer.is_some() {
+            self.trace_instruction();
+        }
+
+        // the opcode and operands are fetched as code
+        self.cdl_access = if self.indirect_jump { cdl::PRG_CODE | cdl::PRG_INDIRECT_CODE } else { cdl::PRG_CODE };
+
         let opcode = self.read(self.pc as usize);
         let mode = self.mode_table[opcode as usize];
 
@@ -277,6 +310,9 @@ impl CPU {
             Mode::IND => {
                 let address = self.read_u16(self.pc as usize + 1);
 
+                // the pointer itself is data
+                self.cdl_access = cdl::PRG_DATA;
+
                 let low = self.read(address as usize) as u16;
                 let high = if address & 0xff == 0xff {
                     self.read(address as usize - 0xff) as u16
@@ -340,8 +376,18 @@ impl CPU {
             mode: mode
         };
 
+        // immediate values and branch offsets are read while executing, but they're still part of
+        // the instruction
+        self.cdl_access = match mode {
+            Mode::IMM | Mode::REL => cdl::PRG_CODE,
+            Mode::IDX | Mode::INX => cdl::PRG_DATA | cdl::PRG_INDIRECT_DATA,
+            _ => cdl::PRG_DATA
+        };
+
         self.opcode_table[opcode as usize](self, info);
 
+        self.indirect_jump = matches!(mode, Mode::IND);
+
         self.cycles - cycles
     }
 
@@ -357,7 +403,15 @@ impl CPU {
                 0
             },
             0x4018..=0x401f => 0, // cpu test mode
-            0x4020..=0xffff => self.mapper.borrow().read(address),
+            0x4020..=0xffff => {
+                if let Some(cdl) = &self.cdl {
+                    if let Some(offset) = self.mapper.borrow().prg_rom_offset(address) {
+                        cdl.borrow_mut().log_prg(offset, address, self.cdl_access);
+                    }
+                }
+
+                self.mapper.borrow().read(address)
+            },
             _ => {
                 println!("Invalid read: 0x{:X}", address);
                 0
@@ -365,6 +419,43 @@ impl CPU {
         }
     }
 
+    // read without any of the side effects a real bus access has (e.g. clearing vblank on a
+    // $2002 read), for debugging purposes
+    pub fn peek(&self, address: usize) -> u8 {
+        match address {
+            0x0000..=0x1fff => self.memory[address % 0x0800],
+            0x4020..=0xffff => self.mapper.borrow().peek(address),
+            _ => 0
+        }
+    }
+
+    // label for a cpu address through the mapper's current bank layout
+    pub fn label(&self, address: u16) -> Option<&str> {
+        if self.symbols.is_empty() {
+            return None;
+        }
+
+        let prg_offset = self.mapper.borrow().prg_rom_offset(address as usize);
+        self.symbols.label(address, prg_offset)
+    }
+
+    // true if the next instruction to be executed is on a breakpoint
+    pub fn hit_breakpoint(&self) -> bool {
+        if self.breakpoints.is_empty() {
+            return false;
+        }
+
+        let prg_offset = self.mapper.borrow().prg_rom_offset(self.pc as usize);
+        self.breakpoints.iter().any(|breakpoint| match *breakpoint {
+            Location::Cpu(address) => address == self.pc,
+            Location::PrgRom(offset) => Some(offset) == prg_offset
+        })
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
     fn read_u16(&mut self, address: usize) -> u16 {
         (self.read(address.wrapping_add(1)) as u16) << 8 | (self.read(address) as u16)
     }
@@ -400,6 +491,11 @@ impl CPU {
     fn write_ppu_register(&mut self, address: usize, value: u8) {
         self.ppu.data_buffer = value;
 
+        // some mappers snoop the ppu's control registers, e.g. MMC5 needs to know the sprite size
+        if address == 0x2000 || address == 0x2001 {
+            self.mapper.borrow_mut().ppu_register_write(address, value);
+        }
+
         match address {
             0x2000 => self.ppu.write_control(value),
             0x2001 => self.ppu.write_mask(value),
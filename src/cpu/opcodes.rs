@@ -407,9 +407,10 @@ impl CPU {
         self.cycles += 7;
     }
 
-    pub fn _irq(&mut self) {
+    pub fn irq(&mut self) {
         self.push_u16(self.pc);
-        self.push(u8::from(self.p) & !0x30);
+        // bit 5 is always set when pushed, the break flag is only set by BRK/PHP
+        self.push((u8::from(self.p) | 0x20) & !0x10);
         self.p.interrupt = true;
         self.pc = self.read_u16(0xfffe);
         self.cycles += 7;
@@ -0,0 +1,99 @@
+use crate::cpu::CPU;
+use crate::cpu::disasm::disassemble;
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// restricts which instructions end up in the trace log
+//
+// all filters are optional and inclusive, an instruction is only logged if it passes all of them
+#[derive(Default)]
+pub struct TraceFilter {
+    pub pc_range: Option<(u16, u16)>,
+    pub frame_range: Option<(u64, u64)>,
+    pub start_address: Option<u16> // don't log anything until this address is executed
+}
+
+pub struct Tracer {
+    // tracing every instruction produces a lot of output, so buffer it generously to avoid a
+    // syscall per line
+    writer: BufWriter<File>,
+    filter: TraceFilter,
+    started: bool
+}
+
+impl Tracer {
+    pub fn new(file_path: &str, filter: TraceFilter) -> io::Result<Tracer> {
+        let file = File::create(file_path)?;
+
+        Ok(Tracer {
+            writer: BufWriter::with_capacity(1 << 20, file),
+            started: filter.start_address.is_none(),
+            filter: filter
+        })
+    }
+
+    fn should_log(&mut self, pc: u16, frame: u64) -> bool {
+        if !self.started && Some(pc) == self.filter.start_address {
+            self.started = true;
+        }
+
+        let in_pc_range = match self.filter.pc_range {
+            Some((start, end)) => pc >= start && pc <= end,
+            None => true
+        };
+
+        let in_frame_range = match self.filter.frame_range {
+            Some((start, end)) => frame >= start && frame <= end,
+            None => true
+        };
+
+        self.started && in_pc_range && in_frame_range
+    }
+
+    fn write_line(&mut self, line: &str) {
+        writeln!(self.writer, "{}", line).expect("Failed to write to trace file");
+    }
+}
+
+impl CPU {
+    // log the instruction at pc before it is executed
+    //
+    // the line layout follows the nestest log so traces can be diffed against other emulators:
+    // C000  4C F5 C5  JMP $C5F5    A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+    pub fn trace_instruction(&mut self) {
+        let pc = self.pc;
+        let frame = self.ppu.frame();
+
+        let should_log = match self.tracer.as_mut() {
+            Some(tracer) => tracer.should_log(pc, frame),
+            None => false
+        };
+
+        if !should_log {
+            return;
+        }
+
+        let instruction = disassemble(|a| self.peek(a as usize), pc);
+        let bytes: Vec<String> = instruction.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+
+        // with symbols loaded, labels get their own line and replace operand addresses
+        let pc_label = self.label(pc).map(String::from);
+        let operand_label = instruction.target().and_then(|target| self.label(target));
+        let text = instruction.format_with_label(operand_label);
+
+        let line = format!(
+            "{:04X}  {:<8}  {:<30}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            pc, bytes.join(" "), text,
+            self.a, self.x, self.y, u8::from(self.p), self.sp,
+            self.ppu.scanline(), self.ppu.cycle(), self.cycles
+        );
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            if let Some(label) = pc_label {
+                tracer.write_line(&format!("{}:", label));
+            }
+            tracer.write_line(&line);
+        }
+    }
+}
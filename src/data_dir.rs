@@ -0,0 +1,37 @@
+use crate::checksum::sha1;
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+// everything znes writes for a game lives in a folder of the data directory named after the
+// SHA-1 of its rom, so it doesn't matter where the rom is, what it's called or whether that
+// directory is writable
+//
+// <data dir>/<sha1>/
+//   battery.sav
+//
+// the rom is hashed without its header, so fixing the header doesn't lose the data either. a
+// patched rom is a different game and gets its own folder
+
+// $XDG_DATA_HOME/znes, which is ~/.local/share/znes unless set (%APPDATA%\znes on windows)
+//
+// https://specifications.freedesktop.org/basedir-spec/latest/
+pub fn default_data_dir() -> PathBuf {
+    let base = if let Some(dir) = env::var_os("XDG_DATA_HOME").filter(|dir| Path::new(dir).is_absolute()) {
+        PathBuf::from(dir)
+    } else if let Some(home) = env::var_os("HOME") {
+        Path::new(&home).join(".local").join("share")
+    } else if let Some(app_data) = env::var_os("APPDATA") {
+        // windows
+        PathBuf::from(app_data)
+    } else {
+        PathBuf::from(".")
+    };
+
+    base.join("znes")
+}
+
+pub fn game_dir(data_dir: &Path, rom: &[u8]) -> PathBuf {
+    let hash: String = sha1(rom).iter().map(|byte| format!("{:02x}", byte)).collect();
+    data_dir.join(hash)
+}
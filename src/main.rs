@@ -1,30 +1,47 @@
 extern crate clap;
 extern crate cpuprofiler;
+extern crate flate2;
 extern crate sdl2;
+extern crate zip;
 
+mod archive;
+mod audio;
 mod cartridge;
+mod cdl;
+mod checksum;
 mod controller;
 mod cpu;
+mod data_dir;
 mod nes;
+mod patch;
 mod ppu;
+mod region;
+mod symbols;
 
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches};
 
 use cpuprofiler::PROFILER;
 
 use nes::NES;
+use cartridge::{Cartridge, LoadOptions, get_mapper};
+use cpu::disasm::{disassemble, disassemble_range, Instruction};
+use cpu::trace::{TraceFilter, Tracer};
+use symbols::{Location, SymbolTable};
+use region::Region;
 
 pub const PIXEL_WIDTH: u32 = 256;
 pub const PIXEL_HEIGHT: u32 = 240;
 
 fn main() {
     let matches = App::new("znes")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("file") // positional argument
-                .about("The .nes file to be ran by the emulator")
+                .about("The .nes, .fds or .unf file to be ran by the emulator, or a .zip/.gz archive holding one")
                 .index(1)
                 .required(true),
         )
@@ -40,8 +57,159 @@ fn main() {
                 .multiple(false)
                 .about("Turn debugging information on"),
         )
+        .arg(
+            Arg::with_name("no-sprite-limit") // draw more than 8 sprites per scanline
+                .long("no-sprite-limit")
+                .about("Draw every sprite on a scanline instead of the first 8, which removes most flicker"),
+        )
+        .arg(
+            Arg::with_name("fds-bios") // disk system bios
+                .long("fds-bios")
+                .takes_value(true)
+                .about("The FDS BIOS to run .fds disk images with, defaults to disksys.rom next to the image"),
+        )
+        .arg(
+            Arg::with_name("patch") // ips/ups/bps patch
+                .long("patch")
+                .takes_value(true)
+                .about("Apply an .ips, .ups or .bps patch, defaults to one named after the rom next to it"),
+        )
+        .arg(
+            Arg::with_name("db") // header database
+                .long("db")
+                .takes_value(true)
+                .about("Correct iNES 1.0 headers from the given NES 2.0 XML database (nes20db.xml), none are corrected without it"),
+        )
+        .arg(
+            Arg::with_name("data-dir") // battery saves and other per-game data
+                .long("data-dir")
+                .takes_value(true)
+                .about("Keep battery saves and other game data here, defaults to $XDG_DATA_HOME/znes"),
+        )
+        .arg(
+            Arg::with_name("region") // console timing
+                .long("region")
+                .takes_value(true)
+                .possible_values(&["ntsc", "pal", "dendy"])
+                .about("Run with NTSC, PAL or Dendy timing, defaults to the region in the rom's header"),
+        )
+        .arg(
+            Arg::with_name("cdl") // code/data logger
+                .long("cdl")
+                .takes_value(true)
+                .about("Log which prg/chr bytes are code, data or rendered to the given .cdl file"),
+        )
+        .arg(
+            Arg::with_name("symbols") // label files
+                .long("symbols")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .about("Load labels from an ld65 .dbg, FCEUX .nl or Mesen .mlb file"),
+        )
+        .arg(
+            Arg::with_name("break") // breakpoints
+                .long("break")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .about("Pause when the given address or label is executed, space resumes"),
+        )
+        .arg(
+            Arg::with_name("trace") // instruction trace log
+                .long("trace")
+                .takes_value(true)
+                .about("Write a log line for every executed instruction to the given file"),
+        )
+        .arg(
+            Arg::with_name("trace-pc") // trace pc filter
+                .long("trace-pc")
+                .takes_value(true)
+                .requires("trace")
+                .about("Only trace instructions in the given address range, e.g. $8000-$80FF"),
+        )
+        .arg(
+            Arg::with_name("trace-frames") // trace frame filter
+                .long("trace-frames")
+                .takes_value(true)
+                .requires("trace")
+                .about("Only trace instructions in the given frame range, e.g. 60-120"),
+        )
+        .arg(
+            Arg::with_name("trace-from") // trace start address
+                .long("trace-from")
+                .takes_value(true)
+                .requires("trace")
+                .about("Start tracing once the given address is executed"),
+        )
+        .subcommand(
+            App::new("disasm")
+                .about("Disassembles the prg rom of a .nes file")
+                .arg(
+                    Arg::with_name("file")
+                        .about("The .nes file to be disassembled, or a .zip/.gz archive holding one")
+                        .index(1)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("bank") // 16 KiB prg bank
+                        .long("bank")
+                        .takes_value(true)
+                        .about("16 KiB prg bank to disassemble, defaults to the mapper's power-on layout"),
+                )
+                .arg(
+                    Arg::with_name("addr") // starting address
+                        .long("addr")
+                        .takes_value(true)
+                        .about("Address to start disassembling from, e.g. $8000"),
+                )
+                .arg(
+                    Arg::with_name("count") // number of instructions
+                        .short('n')
+                        .long("count")
+                        .takes_value(true)
+                        .about("Number of instructions to disassemble, defaults to 32"),
+                )
+                .arg(
+                    Arg::with_name("symbols") // label files
+                        .long("symbols")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .about("Load labels from an ld65 .dbg, FCEUX .nl or Mesen .mlb file"),
+                )
+                .arg(
+                    Arg::with_name("fds-bios") // disk system bios
+                        .long("fds-bios")
+                        .takes_value(true)
+                        .about("The FDS BIOS to load .fds disk images with, defaults to disksys.rom next to the image"),
+                )
+                .arg(
+                    Arg::with_name("patch") // ips/ups/bps patch
+                        .long("patch")
+                        .takes_value(true)
+                        .about("Apply an .ips, .ups or .bps patch, defaults to one named after the rom next to it"),
+                )
+                .arg(
+                    Arg::with_name("db") // header database
+                        .long("db")
+                        .takes_value(true)
+                        .about("Correct iNES 1.0 headers from the given NES 2.0 XML database (nes20db.xml)"),
+                )
+                .arg(
+                    Arg::with_name("data-dir") // battery saves and other per-game data
+                        .long("data-dir")
+                        .takes_value(true)
+                        .about("Read battery saves from here, defaults to $XDG_DATA_HOME/znes"),
+                ),
+        )
         .get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("disasm") {
+        disasm(matches);
+        return;
+    }
+
     let file = matches.value_of("file").unwrap();
 
     let scaling = matches.value_of_t("scale").unwrap_or(3);
@@ -75,7 +243,36 @@ fn main() {
         .unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    let mut nes = NES::new(String::from(file), scaling);
+    // keep running without sound if there's no audio device
+    let audio_queue = open_audio(&sdl_context);
+
+    let mut nes = NES::new(String::from(file), scaling, load_options(&matches));
+
+    nes.cpu.ppu.sprite_limit = !matches.is_present("no-sprite-limit");
+    nes.cpu.symbols = load_symbols(&matches);
+    if let Some(breakpoints) = matches.values_of("break") {
+        for breakpoint in breakpoints {
+            let location = parse_location(breakpoint, &nes.cpu.symbols);
+            nes.cpu.breakpoints.push(location);
+        }
+    }
+
+    if let Some(cdl_file) = matches.value_of("cdl") {
+        nes.enable_cdl(String::from(cdl_file));
+    }
+
+    if let Some(trace_file) = matches.value_of("trace") {
+        let filter = TraceFilter {
+            pc_range: matches.value_of("trace-pc").map(|r| parse_range(r, parse_address)),
+            frame_range: matches.value_of("trace-frames").map(|r| parse_range(r, parse_frame)),
+            start_address: matches.value_of("trace-from").map(parse_address)
+        };
+
+        match Tracer::new(trace_file, filter) {
+            Ok(tracer) => nes.cpu.tracer = Some(tracer),
+            Err(_) => panic!("Cannot create trace file! {}", trace_file)
+        }
+    }
 
     if debug_mode {
         PROFILER.lock().unwrap().start("./znes.profile").unwrap();
@@ -83,7 +280,7 @@ fn main() {
 
     'running: loop {
         let cpu_cycles = nes.step_cpu();
-        let ppu_cycles = cpu_cycles * 3;
+        let ppu_cycles = nes.ppu_cycles(cpu_cycles);
 
         for _ in 0..ppu_cycles {
             nes.step_ppu();
@@ -100,12 +297,30 @@ fn main() {
                 canvas.copy(&texture, None, None).unwrap();
                 canvas.present();
 
+                let samples = nes.take_audio_samples();
+                if let Some(queue) = &audio_queue {
+                    // the video timing drives emulation, so drop samples instead of letting the
+                    // queue (and the audio latency) grow when the two drift apart. the size is in
+                    // bytes, so this allows ~100 ms of 4 byte samples
+                    if queue.size() < audio::SAMPLE_RATE * 4 / 10 {
+                        queue.queue(&samples);
+                    }
+                }
+
                 nes.limit_framerate();
             }
         }
 
+        // stop before executing an instruction on a breakpoint
+        let mut pause = nes.cpu.hit_breakpoint();
+        if pause {
+            let pc = nes.cpu.pc();
+            let instruction = disassemble(|a| nes.cpu.peek(a as usize), pc);
+            let label = nes.cpu.label(pc).unwrap_or("");
+            println!("Breakpoint hit: ${:04X} {} {}", pc, label, instruction);
+        }
+
         // TODO - I think this is a performance regression
-        let mut pause = false;
         'poll: loop {
             for event in event_pump.poll_iter() {
                 match event {
@@ -120,6 +335,14 @@ fn main() {
                     } => {
                         pause = !pause;
                     }
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F5),
+                        ..
+                    } => nes.switch_disk_side(),
+                    Event::KeyDown {
+                        keycode: Some(Keycode::F6),
+                        ..
+                    } => nes.eject_disk(),
                     _ => {}
                 }
             }
@@ -144,4 +367,155 @@ fn main() {
     }
 
     nes.save_battery();
+    nes.save_cdl();
+}
+
+fn open_audio(sdl_context: &sdl2::Sdl) -> Option<AudioQueue<f32>> {
+    let desired = AudioSpecDesired {
+        freq: Some(audio::SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: Some(1024)
+    };
+
+    let queue = sdl_context.audio().and_then(|audio| audio.open_queue::<f32, _>(None, &desired));
+    match queue {
+        Ok(queue) => {
+            queue.resume();
+            Some(queue)
+        },
+        Err(e) => {
+            println!("Cannot open audio device! {}", e);
+            None
+        }
+    }
+}
+
+// parse an address given on the command line, accepting $c0a4, 0xc0a4 or plain decimal
+fn parse_address(text: &str) -> u16 {
+    let parsed = if let Some(hex) = text.strip_prefix('$') {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(hex) = text.strip_prefix("0x") {
+        u16::from_str_radix(hex, 16)
+    } else {
+        text.parse()
+    };
+
+    match parsed {
+        Ok(address) => address,
+        Err(_) => panic!("Invalid address! {}", text)
+    }
+}
+
+fn parse_frame(text: &str) -> u64 {
+    match text.parse() {
+        Ok(frame) => frame,
+        Err(_) => panic!("Invalid frame! {}", text)
+    }
+}
+
+// parse an inclusive range in the form start-end
+fn parse_range<T, F: Fn(&str) -> T>(text: &str, parse: F) -> (T, T) {
+    match text.find('-') {
+        Some(i) => (parse(&text[..i]), parse(&text[i + 1..])),
+        None => panic!("Invalid range! {}", text)
+    }
+}
+
+// a breakpoint is either an address or the name of a loaded label
+fn parse_location(text: &str, symbols: &SymbolTable) -> Location {
+    let is_address = text.starts_with('$') || text.starts_with("0x") || text.chars().all(|c| c.is_ascii_digit());
+
+    if is_address {
+        Location::Cpu(parse_address(text))
+    } else {
+        match symbols.location(text) {
+            Some(location) => location,
+            None => panic!("Unknown label! {}", text)
+        }
+    }
+}
+
+fn load_symbols(matches: &ArgMatches) -> SymbolTable {
+    let mut symbols = SymbolTable::new();
+
+    if let Some(files) = matches.values_of("symbols") {
+        for file in files {
+            if let Err(e) = symbols.load(file) {
+                panic!("{}", e);
+            }
+        }
+    }
+
+    symbols
+}
+
+// the rom loading arguments, shared by running and disassembling
+fn load_options(matches: &ArgMatches) -> LoadOptions {
+    LoadOptions {
+        fds_bios: matches.value_of("fds-bios").map(String::from),
+        patch: matches.value_of("patch").map(String::from),
+        database: matches.value_of("db").map(String::from),
+        data_dir: matches.value_of("data-dir").map(String::from),
+        region: matches.value_of("region").and_then(Region::from_name)
+    }
+}
+
+// where an address lands in prg rom, for label lookups
+type PrgOffset = Box<dyn Fn(u16) -> Option<usize>>;
+
+fn disasm(matches: &ArgMatches) {
+    let options = load_options(matches);
+    let (buffer, file) = archive::read_rom(matches.value_of("file").unwrap());
+    let buffer = patch::patch_rom(buffer, &file, options.patch.as_deref());
+
+    let count = matches.value_of_t("count").unwrap_or(32);
+    let address = matches.value_of("addr").map(parse_address);
+
+    let symbols = load_symbols(matches);
+
+    // instructions, and a way to find where an address lands in prg rom for label lookups
+    let (instructions, prg_offset): (Vec<Instruction>, PrgOffset) = if matches.is_present("bank") {
+        let bank: usize = matches.value_of_t_or_exit("bank");
+        let cart = Cartridge::new(buffer, file.clone());
+        if cart.prg_bank_count() == 0 {
+            panic!("No prg rom banks to disassemble! {}", file);
+        }
+        let bank = bank % cart.prg_bank_count();
+
+        // the 16 KiB switching mappers fix the last prg bank at $c000, every other bank can only
+        // ever be switched into $8000. the odd chunks of 32 KiB banks need an explicit --addr
+        let base: u16 = if bank == cart.prg_bank_count() - 1 { 0xc000 } else { 0x8000 };
+        let data = cart.prg_bank(bank);
+
+        let instructions = disassemble_range(|a| data[a.wrapping_sub(base) as usize % data.len()], address.unwrap_or(base), count);
+        let prg_offset = move |a: u16| {
+            if a >= base && a - base < 0x4000 {
+                Some(bank * 0x4000 + (a - base) as usize)
+            } else {
+                None
+            }
+        };
+
+        (instructions, Box::new(prg_offset))
+    } else {
+        // read through the mapper so the disassembly reflects its bank layout
+        let mapper = get_mapper(buffer, file.clone(), &options);
+        let read = |a: u16| mapper.borrow().peek(a as usize);
+
+        // default to the reset vector
+        let address = address.unwrap_or_else(|| (read(0xfffd) as u16) << 8 | read(0xfffc) as u16);
+
+        let instructions = disassemble_range(read, address, count);
+        (instructions, Box::new(move |a: u16| mapper.borrow().prg_rom_offset(a as usize)))
+    };
+
+    for instruction in instructions {
+        if let Some(label) = symbols.label(instruction.address, prg_offset(instruction.address)) {
+            println!("{}:", label);
+        }
+
+        let operand_label = instruction.target().and_then(|target| symbols.label(target, prg_offset(target)));
+        let bytes: Vec<String> = instruction.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+        println!("${:04X}  {:<8}  {}", instruction.address, bytes.join(" "), instruction.format_with_label(operand_label));
+    }
 }
@@ -2,13 +2,18 @@ use sdl2::keyboard::Scancode;
 
 use crate::cpu::CPU;
 use crate::ppu::{PPU, Color};
-use crate::cartridge::{Mapper, get_mapper};
+use crate::cartridge::{Mapper, LoadOptions, get_mapper};
 use crate::controller;
+use crate::cdl::CodeDataLogger;
+use crate::audio::{Mixer, SAMPLE_RATE};
+use crate::archive::read_rom;
+use crate::patch::patch_rom;
+use crate::region::Region;
 
-use std::fs;
-
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 use std::cell::RefCell;
+use std::panic;
+use std::sync::Once;
 
 use std::time::{Instant, Duration};
 use std::thread::sleep;
@@ -17,6 +22,40 @@ use std::collections::HashSet;
 use crate::PIXEL_WIDTH;
 use crate::PIXEL_HEIGHT;
 
+// battery ram is saved every few seconds of emulated time, if it changed
+const AUTOSAVE_SECONDS: f64 = 5.0;
+
+thread_local! {
+    // the running console's mapper, so the panic hook can still save battery ram
+    static PANIC_MAPPER: RefCell<Option<Weak<RefCell<dyn Mapper>>>> = RefCell::new(None);
+}
+
+static PANIC_HOOK: Once = Once::new();
+
+// save battery ram when the emulator panics, after the default hook prints the message. the mapper
+// can't be saved if the panic happened while it was borrowed mutably (e.g. in one of its writes)
+fn set_panic_hook(mapper: &Rc<RefCell<dyn Mapper>>) {
+    PANIC_MAPPER.with(|panic_mapper| *panic_mapper.borrow_mut() = Some(Rc::downgrade(mapper)));
+
+    PANIC_HOOK.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+
+            let _ = PANIC_MAPPER.try_with(|panic_mapper| {
+                let mapper = match panic_mapper.try_borrow().ok().and_then(|m| m.as_ref()?.upgrade()) {
+                    Some(mapper) => mapper,
+                    None => return
+                };
+                match mapper.try_borrow() {
+                    Ok(mapper) => mapper.save_battery(),
+                    Err(_) => println!("Cannot save battery ram, the mapper was in use when the emulator crashed!")
+                };
+            });
+        }));
+    });
+}
+
 // isolate the actual nes console into it's own struct
 //
 // do not handle anything to do with sdl in here as defeats it the purpose of having a class to
@@ -28,35 +67,77 @@ pub struct NES {
     pub screen_buffer: Vec<u8>,
 
     mapper: Rc<RefCell<dyn Mapper>>,
+    cdl: Option<Rc<RefCell<CodeDataLogger>>>,
+    mixer: Mixer,
+
+    region: Region,
+    ppu_remainder: u64, // PAL's fraction of a ppu dot left over from the last instruction
 
     scaling: u32,
     timer: Instant,
+    autosave_cycles: u64
 }
 
 impl NES {
-    pub fn new(file_path: String, scaling: u32) -> NES {
-        let buffer = fs::read(file_path.clone());
-        let buffer = match buffer {
-            Ok(b) => b,
-            Err(_) => panic!("Cannot load rom! {}", file_path)
-        };
+    pub fn new(file_path: String, scaling: u32, options: LoadOptions) -> NES {
+        let (buffer, file_path) = read_rom(&file_path);
+        let buffer = patch_rom(buffer, &file_path, options.patch.as_deref());
 
-        let mapper = get_mapper(buffer, file_path);
-        let ppu = PPU::new(mapper.clone());
+        let mapper = get_mapper(buffer, file_path, &options);
+        let region = options.region.unwrap_or_else(|| mapper.borrow().cartridge().region());
+        println!("Region: {:?}", region);
+
+        let ppu = PPU::new(mapper.clone(), region);
+        set_panic_hook(&mapper);
 
         NES {
             cpu: CPU::new(mapper.clone(), ppu),
             screen_buffer: vec![0; (PIXEL_WIDTH * scaling * 3 * PIXEL_HEIGHT * scaling) as usize],
 
             mapper: mapper,
+            cdl: None,
+            mixer: Mixer::new(region.cpu_frequency(), SAMPLE_RATE),
+
+            region: region,
+            ppu_remainder: 0,
 
             scaling: scaling,
-            timer: Instant::now()
+            timer: Instant::now(),
+            autosave_cycles: 0
         }
     }
 
     pub fn step_cpu(&mut self) -> u64 {
-        self.cpu.step()
+        let cycles = self.cpu.step();
+
+        // mappers with irq counters or expansion audio are clocked along with the cpu
+        let mut mapper = self.mapper.borrow_mut();
+        for _ in 0..cycles {
+            mapper.step();
+            self.mixer.push(mapper.audio_output());
+        }
+
+        self.autosave_cycles += cycles;
+        if self.autosave_cycles as f64 >= self.region.cpu_frequency() * AUTOSAVE_SECONDS {
+            self.autosave_cycles = 0;
+            mapper.save_battery();
+        }
+
+        cycles
+    }
+
+    // ppu dots to run for the cpu cycles of the last instruction, 3 per cycle except for PAL's 3.2
+    pub fn ppu_cycles(&mut self, cpu_cycles: u64) -> u64 {
+        let (dots, cycles) = self.region.ppu_clock_ratio();
+        let total = cpu_cycles * dots + self.ppu_remainder;
+        self.ppu_remainder = total % cycles;
+
+        total / cycles
+    }
+
+    // audio samples generated since the last call, at SAMPLE_RATE
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.mixer.take_samples()
     }
 
     pub fn step_ppu(&mut self) {
@@ -88,9 +169,10 @@ impl NES {
 
     // sleep the thread if running too quickly
     pub fn limit_framerate(&mut self) {
+        let frame_time = Duration::from_secs_f64(1.0 / self.region.frame_rate());
         let now = Instant::now();
-        if now < self.timer + Duration::from_millis(1000 / 60) {
-            sleep(self.timer + Duration::from_millis(1000/60) - now);
+        if now < self.timer + frame_time {
+            sleep(self.timer + frame_time - now);
         }
         self.timer = Instant::now();
     }
@@ -123,7 +205,35 @@ impl NES {
         self.cpu.controllers[0].set_buttons(buttons);
     }
 
+    pub fn switch_disk_side(&mut self) {
+        self.mapper.borrow_mut().switch_disk_side();
+    }
+
+    pub fn eject_disk(&mut self) {
+        self.mapper.borrow_mut().eject_disk();
+    }
+
     pub fn save_battery(&self) {
         self.mapper.borrow().save_battery();
     }
+
+    // start logging prg/chr accesses, the cpu and ppu share the same logger
+    pub fn enable_cdl(&mut self, file_path: String) {
+        let (prg_size, chr_size) = {
+            let mapper = self.mapper.borrow();
+            let cart = mapper.cartridge();
+            (cart.prg_rom_len(), cart.chr_rom_len())
+        };
+
+        let cdl = Rc::new(RefCell::new(CodeDataLogger::new(file_path, prg_size, chr_size)));
+        self.cpu.cdl = Some(cdl.clone());
+        self.cpu.ppu.cdl = Some(cdl.clone());
+        self.cdl = Some(cdl);
+    }
+
+    pub fn save_cdl(&self) {
+        if let Some(cdl) = &self.cdl {
+            cdl.borrow().save();
+        }
+    }
 }
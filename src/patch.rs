@@ -0,0 +1,454 @@
+use crate::checksum::crc32;
+
+use std::fs;
+use std::path::Path;
+
+const MAX_TARGET_SIZE: usize = 1 << 26; // 64 MiB
+
+// soft-patching roms at load time, for translations and hacks kept separately from the rom
+//
+// a patch is applied to the raw file before it's parsed, either one given on the command line or
+// a .ips, .ups or .bps file with the same name as the rom next to it
+pub fn patch_rom(buffer: Vec<u8>, file_path: &str, patch_path: Option<&str>) -> Vec<u8> {
+    let patch_path = match patch_path {
+        Some(path) => String::from(path),
+        None => {
+            let found = ["ips", "ups", "bps"].iter()
+                .map(|extension| Path::new(file_path).with_extension(extension))
+                .find(|path| path.is_file());
+            match found {
+                Some(path) => path.to_string_lossy().into_owned(),
+                None => return buffer
+            }
+        }
+    };
+
+    let patch = match fs::read(&patch_path) {
+        Ok(p) => p,
+        Err(_) => panic!("Cannot load patch! {}", patch_path)
+    };
+
+    match apply(buffer, &patch) {
+        Ok(buffer) => {
+            println!("Applied patch: {}", patch_path);
+            buffer
+        },
+        Err(e) => panic!("Cannot apply patch! {} {}", patch_path, e)
+    }
+}
+
+// the format is told apart by the patch's signature
+pub fn apply(mut data: Vec<u8>, patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(&mut data, patch)?;
+        Ok(data)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(&data, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(&data, patch)
+    } else {
+        Err(String::from("Unknown patch format!"))
+    }
+}
+
+fn truncated() -> String {
+    String::from("Truncated patch!")
+}
+
+fn malformed() -> String {
+    String::from("Malformed patch number!")
+}
+
+// https://zerosoft.zophar.net/ips.php
+//
+// IPS patches are a list of records replacing the bytes at an offset, each either a run of
+// literal bytes or a single byte repeated. there's no checksum, so any rom is accepted
+//
+// "PATCH", records, "EOF", optionally a 3 byte size to truncate the data to (a Lunar IPS extension)
+// record: 3 byte offset, 2 byte size, data
+// rle record: 3 byte offset, 0, 2 byte count, value
+pub fn apply_ips(data: &mut Vec<u8>, patch: &[u8]) -> Result<(), String> {
+    if !patch.starts_with(b"PATCH") {
+        return Err(String::from("Incorrect IPS signature!"));
+    }
+
+    let mut i = 5;
+    loop {
+        let record = patch.get(i..i + 3).ok_or_else(truncated)?;
+        if record == b"EOF" {
+            if let Some(size) = patch.get(i + 3..i + 6) {
+                data.truncate((size[0] as usize) << 16 | (size[1] as usize) << 8 | size[2] as usize);
+            }
+            return Ok(());
+        }
+
+        let offset = (record[0] as usize) << 16 | (record[1] as usize) << 8 | record[2] as usize;
+        let size = patch.get(i + 3..i + 5).ok_or_else(truncated)?;
+        let size = (size[0] as usize) << 8 | size[1] as usize;
+        i += 5;
+
+        if size == 0 {
+            let rle = patch.get(i..i + 3).ok_or_else(truncated)?;
+            let count = (rle[0] as usize) << 8 | rle[1] as usize;
+            if data.len() < offset + count {
+                data.resize(offset + count, 0);
+            }
+            for byte in &mut data[offset..offset + count] {
+                *byte = rle[2];
+            }
+            i += 3;
+        } else {
+            let bytes = patch.get(i..i + size).ok_or_else(truncated)?;
+            if data.len() < offset + size {
+                data.resize(offset + size, 0);
+            }
+            data[offset..offset + size].copy_from_slice(bytes);
+            i += size;
+        }
+    }
+}
+
+// a record for every run of changed bytes, the data has to be smaller than the 16 MiB the offsets
+// can address
+pub fn create_ips(original: &[u8], modified: &[u8]) -> Vec<u8> {
+    let mut patch = b"PATCH".to_vec();
+    let changed = |i: usize| original.get(i) != Some(&modified[i]);
+
+    let mut i = 0;
+    while i < modified.len() {
+        if !changed(i) {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while i < modified.len() && i - start < 0xffff && changed(i) {
+            i += 1;
+        }
+
+        patch.extend_from_slice(&[(start >> 16) as u8, (start >> 8) as u8, start as u8]);
+        patch.extend_from_slice(&[((i - start) >> 8) as u8, (i - start) as u8]);
+        patch.extend_from_slice(&modified[start..i]);
+    }
+
+    patch.extend_from_slice(b"EOF");
+    patch
+}
+
+// UPS and BPS numbers are variable length, 7 bits per byte with the high bit set on the last. a
+// broken patch can encode numbers that don't fit, which are refused instead of overflowing
+fn read_number(patch: &[u8], i: &mut usize) -> Result<usize, String> {
+    let mut number: usize = 0;
+    let mut shift: usize = 1;
+    loop {
+        let byte = *patch.get(*i).ok_or_else(truncated)? as usize;
+        *i += 1;
+
+        number = (byte & 0x7f).checked_mul(shift).and_then(|n| number.checked_add(n)).ok_or_else(malformed)?;
+        if byte & 0x80 != 0 {
+            return Ok(number);
+        }
+        shift = shift.checked_mul(0x80).ok_or_else(malformed)?;
+        number = number.checked_add(shift).ok_or_else(malformed)?;
+    }
+}
+
+// both formats end with the crc32s of the source, the target and the patch itself
+fn read_checksums(patch: &[u8]) -> Result<(u32, u32), String> {
+    if patch.len() < 16 {
+        return Err(truncated());
+    }
+
+    let footer = &patch[patch.len() - 12..];
+    let crc = |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+
+    let actual = crc32(&patch[..patch.len() - 4]);
+    if crc(8) != actual {
+        return Err(format!("Patch checksum mismatch, the patch is corrupt (expected {:08X}, got {:08X})", crc(8), actual));
+    }
+
+    Ok((crc(0), crc(4)))
+}
+
+fn verify_source(data: &[u8], expected: u32) -> Result<(), String> {
+    let actual = crc32(data);
+    if actual != expected {
+        return Err(format!("Source checksum mismatch, the patch is for a different rom (expected {:08X}, got {:08X})", expected, actual));
+    }
+    Ok(())
+}
+
+fn verify_target(data: &[u8], expected: u32) -> Result<(), String> {
+    let actual = crc32(data);
+    if actual != expected {
+        return Err(format!("Target checksum mismatch, the patched rom is wrong (expected {:08X}, got {:08X})", expected, actual));
+    }
+    Ok(())
+}
+
+// https://www.romhacking.net/documents/392/
+//
+// "UPS1", source size, target size, then hunks of a number of bytes to skip followed by bytes to
+// xor with the source, ended by a 0
+fn apply_ups(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let (source_crc, target_crc) = read_checksums(patch)?;
+    verify_source(source, source_crc)?;
+
+    let mut i = 4;
+    let _source_size = read_number(patch, &mut i)?;
+    let target_size = read_number(patch, &mut i)?;
+    check_target_size(target_size)?;
+
+    let mut target = source.to_vec();
+    target.resize(target_size, 0);
+
+    let end = patch.len() - 12;
+    let mut offset: usize = 0;
+    while i < end {
+        offset = offset.checked_add(read_number(patch, &mut i)?).ok_or_else(malformed)?;
+        loop {
+            let byte = *patch.get(i).ok_or_else(truncated)?;
+            i += 1;
+            if byte == 0 {
+                offset += 1;
+                break;
+            }
+
+            if offset < target.len() {
+                target[offset] ^= byte;
+            }
+            offset += 1;
+        }
+    }
+
+    verify_target(&target, target_crc)?;
+    Ok(target)
+}
+
+// nothing close to this size is a rom, so it's a broken patch rather than a reason to run out of
+// memory
+fn check_target_size(size: usize) -> Result<(), String> {
+    if size > MAX_TARGET_SIZE {
+        return Err(format!("Patched rom would be {} bytes!", size));
+    }
+    Ok(())
+}
+
+// https://www.romhacking.net/documents/746/
+//
+// "BPS1", source size, target size, metadata, then actions building the target from start to end:
+// 0: copy from the source at the same offset, 1: copy from the patch, 2/3: copy from a relative
+// offset in the source/target
+fn apply_bps(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let (source_crc, target_crc) = read_checksums(patch)?;
+    verify_source(source, source_crc)?;
+
+    let mut i = 4;
+    let _source_size = read_number(patch, &mut i)?;
+    let target_size = read_number(patch, &mut i)?;
+    check_target_size(target_size)?;
+    let metadata_size = read_number(patch, &mut i)?;
+    i = i.checked_add(metadata_size).ok_or_else(malformed)?;
+
+    let out_of_range = || String::from("Patch copies from outside the rom!");
+
+    let mut target = Vec::with_capacity(target_size);
+    let mut source_offset: isize = 0;
+    let mut target_offset: isize = 0;
+
+    let end = patch.len() - 12;
+    while i < end {
+        let action = read_number(patch, &mut i)?;
+        let length = (action >> 2) + 1;
+        if target.len() + length > target_size {
+            return Err(format!("Patched rom is bigger than the {} bytes it should be!", target_size));
+        }
+
+        match action & 3 {
+            0 => {
+                let start = target.len();
+                let bytes = source.get(start..start + length).ok_or_else(out_of_range)?;
+                target.extend_from_slice(bytes);
+            },
+            1 => {
+                let bytes = patch.get(i..i.checked_add(length).ok_or_else(truncated)?).ok_or_else(truncated)?;
+                target.extend_from_slice(bytes);
+                i += length;
+            },
+            2 => {
+                source_offset = relative_offset(source_offset, read_number(patch, &mut i)?)?;
+
+                let start = source_offset as usize;
+                let bytes = source.get(start..start + length).ok_or_else(out_of_range)?;
+                target.extend_from_slice(bytes);
+                source_offset += length as isize;
+            },
+            _ => {
+                target_offset = relative_offset(target_offset, read_number(patch, &mut i)?)?;
+
+                // the copy can overlap what it's writing, so it goes a byte at a time
+                for _ in 0..length {
+                    let byte = *target.get(target_offset as usize).ok_or_else(out_of_range)?;
+                    target.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size {
+        return Err(format!("Patched rom is {} bytes instead of {}!", target.len(), target_size));
+    }
+
+    verify_target(&target, target_crc)?;
+    Ok(target)
+}
+
+// BPS offsets are relative to the last copy, with the sign in the lowest bit
+fn relative_offset(offset: isize, relative: usize) -> Result<isize, String> {
+    let distance = (relative >> 1) as isize;
+    let offset = if relative & 1 != 0 { offset.checked_sub(distance) } else { offset.checked_add(distance) };
+    offset.filter(|&offset| offset >= 0).ok_or_else(|| String::from("Patch copies from outside the rom!"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(mut n: usize) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        loop {
+            let low = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                bytes.push(0x80 | low);
+                return bytes;
+            }
+            bytes.push(low);
+            n -= 1;
+        }
+    }
+
+    // the source and target crcs, then the crc of everything before it
+    fn with_checksums(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    // HELLO WORLD -> HELLO THERE
+    fn ups_patch() -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(number(11));
+        patch.extend(number(11));
+        patch.extend(number(6));
+        patch.extend(b"WORLD".iter().zip(b"THERE").map(|(a, b)| a ^ b));
+        patch.push(0);
+        with_checksums(patch, b"HELLO WORLD", b"HELLO THERE")
+    }
+
+    // HELLO WORLD -> HELLO THERE THERE, copying from the source, the patch and the target
+    fn bps_patch() -> Vec<u8> {
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(number(11));
+        patch.extend(number(17));
+        patch.extend(number(0));
+        patch.extend(number((6 - 1) << 2));
+        patch.extend(number((6 - 1) << 2 | 1));
+        patch.extend_from_slice(b"THERE ");
+        patch.extend(number((5 - 1) << 2 | 3));
+        patch.extend(number(6 << 1));
+        with_checksums(patch, b"HELLO WORLD", b"HELLO THERE THERE")
+    }
+
+    #[test]
+    fn numbers_round_trip() {
+        for &n in &[0, 1, 0x7f, 0x80, 0x407f, 0x4080, 123_456_789] {
+            let mut i = 0;
+            assert_eq!(read_number(&number(n), &mut i), Ok(n));
+        }
+    }
+
+    #[test]
+    fn numbers_that_overflow_are_refused() {
+        let mut i = 0;
+        assert_eq!(read_number(&[0x7f; 16], &mut i), Err(malformed()));
+
+        let mut i = 0;
+        assert_eq!(read_number(&[0x7f; 4], &mut i), Err(truncated()));
+    }
+
+    #[test]
+    fn ips_records_replace_bytes() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0, 0, 1, 0, 2, 0xaa, 0xbb]);
+        // run of 3 bytes past the end, which grows the data
+        patch.extend_from_slice(&[0, 0, 4, 0, 0, 0, 3, 0xcc]);
+        patch.extend_from_slice(b"EOF");
+
+        let data = apply(vec![0; 4], &patch).unwrap();
+        assert_eq!(data, [0, 0xaa, 0xbb, 0, 0xcc, 0xcc, 0xcc]);
+    }
+
+    #[test]
+    fn ips_truncates_to_the_size_after_eof() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0, 0, 0, 0, 1, 0xff]);
+        patch.extend_from_slice(b"EOF");
+        patch.extend_from_slice(&[0, 0, 2]);
+
+        assert_eq!(apply(vec![1, 2, 3, 4], &patch).unwrap(), [0xff, 2]);
+    }
+
+    #[test]
+    fn ips_round_trip() {
+        let original: Vec<u8> = (0..=255).collect();
+        let mut modified = original.clone();
+        modified[3] = 0;
+        modified[100..110].copy_from_slice(&[7; 10]);
+        modified.extend_from_slice(&[1, 2, 3]);
+
+        let patch = create_ips(&original, &modified);
+        assert_eq!(apply(original, &patch).unwrap(), modified);
+    }
+
+    #[test]
+    fn truncated_ips_is_refused() {
+        let patch = b"PATCH\x00\x00\x01\x00\x05\xaa".to_vec();
+        assert_eq!(apply(vec![0; 4], &patch), Err(truncated()));
+    }
+
+    #[test]
+    fn ups_xors_the_source() {
+        assert_eq!(apply(b"HELLO WORLD".to_vec(), &ups_patch()).unwrap(), b"HELLO THERE");
+    }
+
+    #[test]
+    fn bps_copies_from_the_source_patch_and_target() {
+        assert_eq!(apply(b"HELLO WORLD".to_vec(), &bps_patch()).unwrap(), b"HELLO THERE THERE");
+    }
+
+    #[test]
+    fn patches_for_another_rom_are_refused() {
+        let error = apply(b"HELLO EARTH".to_vec(), &ups_patch()).unwrap_err();
+        assert!(error.starts_with("Source checksum mismatch"));
+
+        let error = apply(b"HELLO EARTH".to_vec(), &bps_patch()).unwrap_err();
+        assert!(error.starts_with("Source checksum mismatch"));
+    }
+
+    #[test]
+    fn corrupt_patches_are_refused() {
+        let mut patch = ups_patch();
+        patch[7] ^= 1;
+        let error = apply(b"HELLO WORLD".to_vec(), &patch).unwrap_err();
+        assert!(error.starts_with("Patch checksum mismatch"));
+    }
+
+    #[test]
+    fn unknown_formats_are_refused() {
+        assert!(apply(vec![0; 4], b"NOT A PATCH").is_err());
+    }
+}
@@ -3,6 +3,10 @@ mod render;
 
 use crate::cartridge::Mapper;
 use crate::cartridge::Mirror;
+use crate::cartridge::Fetch;
+use crate::cdl;
+use crate::cdl::CodeDataLogger;
+use crate::region::Region;
 
 use std::rc::Rc;
 use std::cell::RefCell;
@@ -31,6 +35,9 @@ pub struct PPU {
 
     mapper: Rc<RefCell<dyn Mapper>>,
 
+    pub cdl: Option<Rc<RefCell<CodeDataLogger>>>,
+    cdl_access: u8, // pattern reads are either rendering fetches or cpu reads through $2007
+
     // background variables
     nametable_byte: u8,
     attribute_table_byte: u8,
@@ -38,11 +45,24 @@ pub struct PPU {
     high_tile_byte: u8,
 
     // sprite variables
+    //
+    // the hardware has room for 8 sprites per scanline, the rest are for drawing every sprite
+    // without the limit
+    pub sprite_limit: bool,
     sprite_count: usize,
-    sprite_attribute_latches: [u8; 8],
-    sprite_positions: [u8; 8],
-    sprite_indexes: [u8; 8],
-    sprite_pattern_shift_regs: [(u8, u8); 8],
+    sprite_attribute_latches: [u8; 64],
+    sprite_positions: [u8; 64],
+    sprite_indexes: [u8; 64],
+    next_sprite_indexes: [u8; 64], // filled by evaluation while the current line still draws
+    sprite_pattern_shift_regs: [(u8, u8); 64],
+    sprite_low_byte: u8, // pattern fetched on dot 5 of a sprite slot, used on dot 7
+
+    // sprite evaluation, see render.rs
+    oam_latch: u8, // oam byte read on odd cycles, used on even cycles
+    eval_n: usize, // sprite in primary oam
+    eval_m: usize, // byte within the sprite
+    eval_found: usize, // sprites copied to secondary oam
+    eval_done: bool, // every sprite has been checked (or the overflow was found)
 
     // background shift registers
     //
@@ -102,12 +122,16 @@ pub struct PPU {
 
     in_vblank: bool,
 
+    region: Region,
+    pre_render_scanline: usize, // 261 for NTSC, 311 for PAL and Dendy
+    idle_dots: u64, // since the last rendering fetch, see Mapper::ppu_idle
+
     // rgb color data
     palette_table: [Color; 0x40]
 }
 
 impl PPU {
-    pub fn new(mapper: Rc<RefCell<dyn Mapper>>) -> PPU {
+    pub fn new(mapper: Rc<RefCell<dyn Mapper>>, region: Region) -> PPU {
         PPU {
             cycle: 0,
             scanline: 0,
@@ -128,16 +152,28 @@ impl PPU {
 
             mapper: mapper,
 
+            cdl: None,
+            cdl_access: cdl::CHR_RENDERED,
+
             nametable_byte: 0,
             attribute_table_byte: 0,
             low_tile_byte: 0,
             high_tile_byte: 0,
 
+            sprite_limit: true,
             sprite_count: 0,
-            sprite_attribute_latches: [0; 8],
-            sprite_positions: [0; 8],
-            sprite_indexes: [0; 8],
-            sprite_pattern_shift_regs: [(0, 0); 8],
+            sprite_attribute_latches: [0; 64],
+            sprite_positions: [0; 64],
+            sprite_indexes: [0; 64],
+            next_sprite_indexes: [0; 64],
+            sprite_pattern_shift_regs: [(0, 0); 64],
+            sprite_low_byte: 0,
+
+            oam_latch: 0,
+            eval_n: 0,
+            eval_m: 0,
+            eval_found: 0,
+            eval_done: false,
 
             pattern_shift_reg_low: 0,
             pattern_shift_reg_high: 0,
@@ -179,6 +215,10 @@ impl PPU {
 
             in_vblank: false,
 
+            region: region,
+            pre_render_scanline: region.pre_render_scanline(),
+            idle_dots: 0,
+
             // hardcoded https://wiki.nesdev.com/w/index.php/PPU_palettes#2C02
             palette_table: [
                 Color(84, 84, 84), Color(0, 30, 116), Color(8, 16, 144), Color(48, 0, 136), Color(68, 0, 100), Color(92, 0, 48), Color(84, 4, 0), Color(60, 24, 0), Color(32, 42, 0), Color(8, 58, 0), Color(0, 64, 0), Color(0, 60, 0), Color(0, 50, 60), Color(0, 0, 0), Color(0, 0, 0), Color(0, 0, 0),
@@ -189,12 +229,25 @@ impl PPU {
         }
     }
 
+    pub fn cycle(&self) -> i32 {
+        self.cycle
+    }
+
+    pub fn scanline(&self) -> usize {
+        self.scanline
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
     pub fn clock(&mut self) {
-        if self.cycle == 339 && self.scanline == 261 && self.frame % 2 == 1 {
+        let odd_frame_skip = self.region.skips_odd_frame_dot() && self.frame % 2 == 1;
+        if self.cycle == 339 && self.scanline == self.pre_render_scanline && odd_frame_skip {
             self.cycle = 0;
             self.scanline = 0;
             self.frame = self.frame.wrapping_add(1);
-        } else if self.cycle == 340 && self.scanline == 261 {
+        } else if self.cycle == 340 && self.scanline == self.pre_render_scanline {
             self.cycle = 0;
             self.scanline = 0;
             self.frame = self.frame.wrapping_add(1);
@@ -222,11 +275,11 @@ impl PPU {
 
         if rendering_enabled {
             // visible scanlines
-            if self.scanline < 240 || self.scanline == 261 {
+            if self.scanline < 240 || self.scanline == self.pre_render_scanline {
                 match self.cycle {
                     0 => (),
                     1..=256 => {
-                        if self.scanline != 261 {
+                        if self.scanline != self.pre_render_scanline {
                             pixel = Some(self.render_pixel());
                         }
                         match self.cycle % 8 {
@@ -248,6 +301,8 @@ impl PPU {
                         // horizontal(v) = horizontal(t)
                         self.v = (self.v & 0xfbe0) | (self.t & 0x041f);
                     },
+                    // two unused nametable fetches end the scanline, MMC5 relies on seeing them
+                    337 | 339 => self.fetch_nametable_byte(),
                     321..=336 => {
                         match self.cycle % 8 {
                             0 => self.increment_coarse_x(),
@@ -269,35 +324,58 @@ impl PPU {
             }
 
             // sprite rendering
+            //
+            // https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+            //
+            // secondary oam is cleared during cycles 1-64 and filled with the sprites on the next
+            // scanline during 65-256, with primary oam read on odd cycles and secondary oam written
+            // on even ones. their patterns are fetched from 257 on
             if self.scanline < 240 {
                 match self.cycle {
-                    1 => self.secondary_oam = [0xff; 0x20],
+                    1 => self.clear_secondary_oam(),
+                    65..=256 if self.cycle % 2 == 1 => self.oam_latch = self.oam_data[self.eval_n * 4 + self.eval_m],
+                    65..=256 => self.evaluate_sprites(),
                     257 => {
-                        self.evaluate_sprites();
-                        self.fetch_sprites();
+                        if self.sprite_limit {
+                            self.sprite_count = self.eval_found;
+                        } else {
+                            self.find_all_sprites();
+                        }
+                        self.sprite_indexes = self.next_sprite_indexes;
                     }
                     _ => ()
                 }
             }
 
+            // the patterns of the 8 sprite slots are fetched during 257-320, 8 dots per slot like
+            // the background tiles (without the two garbage nametable fetches before them). the
+            // pre-render scanline fetches them too, for nothing
+            if self.scanline < 240 || self.scanline == self.pre_render_scanline {
+                match self.cycle {
+                    257..=320 if self.cycle % 8 == 5 => self.fetch_sprite_low((self.cycle - 257) as usize / 8),
+                    257..=320 if self.cycle % 8 == 7 => self.fetch_sprite_high((self.cycle - 257) as usize / 8),
+                    _ => ()
+                }
+            }
+
 
-            if self.scanline == 261 && self.cycle >= 280 && self.cycle <= 304 {
+            if self.scanline == self.pre_render_scanline && self.cycle >= 280 && self.cycle <= 304 {
                 // vertical(v) = vertical(t)
                 self.v = (self.v & 0x841f) | (self.t & 0x7be0);
             }
 
-            if (self.scanline < 240 || self.scanline == 261) && self.cycle == 256 {
+            if (self.scanline < 240 || self.scanline == self.pre_render_scanline) && self.cycle == 256 {
                 self.increment_y();
             }
         }
 
         // vblank logic
-        if self.scanline == 241 && self.cycle == 1 {
+        if self.scanline == self.region.vblank_scanline() && self.cycle == 1 {
             self.in_vblank = true;
             self.nmi_change();
         }
 
-        if self.scanline == 261 && self.cycle == 1 {
+        if self.scanline == self.pre_render_scanline && self.cycle == 1 {
             self.in_vblank = false;
             self.nmi_change();
 
@@ -305,6 +383,14 @@ impl PPU {
             self.sprite_overflow = false;
         }
 
+        // the ppu fetches something at least every 6 dots while rendering
+        if self.idle_dots < self.idle_limit() {
+            self.idle_dots += 1;
+            if self.idle_dots == self.idle_limit() {
+                self.mapper.borrow_mut().ppu_idle();
+            }
+        }
+
         // update end of frame signal
         self.end_of_frame = self.cycle == 256 && self.scanline == 240;
 
@@ -314,10 +400,52 @@ impl PPU {
         pixel
     }
 
+    // dots in 3 cpu cycles, rounded up for PAL
+    fn idle_limit(&self) -> u64 {
+        let (dots, cycles) = self.region.ppu_clock_ratio();
+        (3 * dots).div_ceil(cycles)
+    }
+
+    // a fetch the ppu throws away, which the mapper still sees on the bus
+    fn dummy_read(&mut self, address: usize, fetch: Fetch) {
+        self.idle_dots = 0;
+        self.mapper.borrow_mut().ppu_fetch(address, fetch);
+    }
+
+    // pattern reads are logged after the mapper had its chance to answer them, so the offset is
+    // the one of the chr it actually read from
+    fn log_chr(&self, address: usize, fetch: Fetch) {
+        if let Some(cdl) = &self.cdl {
+            if let Some(offset) = self.mapper.borrow().chr_fetch_offset(address, fetch) {
+                cdl.borrow_mut().log_chr(offset, self.cdl_access);
+            }
+        }
+    }
+
     // PPU's bus read
-    fn read(&mut self, address: usize) -> u8 {
+    //
+    // mappers get to see (and replace) every pattern and nametable fetch along with what it is
+    // for, which is how e.g. MMC5 counts scanlines and implements its extended attributes
+    fn read(&mut self, address: usize, fetch: Fetch) -> u8 {
+        if fetch != Fetch::Cpu {
+            self.idle_dots = 0;
+        }
+
+        if address < 0x3f00 {
+            let value = self.mapper.borrow_mut().ppu_fetch(address, fetch);
+            if let Some(value) = value {
+                if address < 0x2000 {
+                    self.log_chr(address, fetch);
+                }
+                return value;
+            }
+        }
+
         match address {
-            0x0000..=0x1fff => self.mapper.borrow().read(address),
+            0x0000..=0x1fff => {
+                self.log_chr(address, fetch);
+                self.mapper.borrow().read(address)
+            },
             0x2000..=0x3eff => {
                 let address = address & 0x0fff;
                 match self.mapper.borrow().get_mirror() {
@@ -343,7 +471,8 @@ impl PPU {
                         }
                     },
                     Mirror::Single0 => self.nametable_data[0][address & 0x03ff],
-                    Mirror::Single1 => self.nametable_data[1][address & 0x03ff]
+                    Mirror::Single1 => self.nametable_data[1][address & 0x03ff],
+                    Mirror::Custom(pages) => self.nametable_data[pages[address >> 10]][address & 0x03ff]
                 }
             },
             0x3f00..=0x3fff => self.palette_data[address & 0x001f],
@@ -356,6 +485,11 @@ impl PPU {
         match address {
             0x0000..=0x1fff => self.mapper.borrow_mut().write(address, value),
             0x2000..=0x3eff => {
+                // nametables the mapper provides itself
+                if self.mapper.borrow_mut().ppu_write(address, value) {
+                    return;
+                }
+
                 let address = address & 0x0fff;
                 match self.mapper.borrow().get_mirror() {
                     Mirror::Horizontal => {
@@ -380,7 +514,8 @@ impl PPU {
                         }
                     },
                     Mirror::Single0 => self.nametable_data[0][address & 0x03ff] = value,
-                    Mirror::Single1 => self.nametable_data[1][address & 0x03ff] = value
+                    Mirror::Single1 => self.nametable_data[1][address & 0x03ff] = value,
+                    Mirror::Custom(pages) => self.nametable_data[pages[address >> 10]][address & 0x03ff] = value
                 }
             },
             0x3f00..=0x3fff => {
@@ -1,4 +1,6 @@
 use crate::ppu::PPU;
+use crate::cartridge::Fetch;
+use crate::cdl;
 
 // https://wiki.nesdev.com/w/index.php/PPU_registers
 //
@@ -31,7 +33,9 @@ impl PPU {
 
     // $2007 PPUDATA read
     pub fn read_data(&mut self) -> u8 {
-        let mut result = self.read(self.v as usize);
+        self.cdl_access = cdl::CHR_READ;
+        let mut result = self.read(self.v as usize, Fetch::Cpu);
+        self.cdl_access = cdl::CHR_RENDERED;
 
         if self.v % 0x4000 < 0x3f00 {
             let buffered_data = self.read_buffer_data;
@@ -39,10 +43,10 @@ impl PPU {
             result = buffered_data;
         } else {
             // palette address space
-            self.read_buffer_data = self.read(self.v as usize - 0x1000);
+            self.read_buffer_data = self.read(self.v as usize - 0x1000, Fetch::Cpu);
         }
 
-        if (self.show_background || self.show_sprites) && (self.scanline < 240 || self.scanline == 261) {
+        if (self.show_background || self.show_sprites) && (self.scanline < 240 || self.scanline == self.pre_render_scanline) {
             self.increment_coarse_x();
             self.increment_y();
         } else {
@@ -122,7 +126,7 @@ impl PPU {
     pub fn write_data(&mut self, value: u8) {
         self.write(self.v as usize, value);
 
-        if (self.show_background || self.show_sprites) && (self.scanline < 240 || self.scanline == 261) {
+        if (self.show_background || self.show_sprites) && (self.scanline < 240 || self.scanline == self.pre_render_scanline) {
             self.increment_coarse_x();
             self.increment_y();
         } else {
@@ -1,100 +1,211 @@
 use crate::ppu::PPU;
 use crate::ppu::Color;
+use crate::cartridge::Fetch;
 
 impl PPU {
+    pub fn clear_secondary_oam(&mut self) {
+        self.secondary_oam = [0xff; 0x20];
+
+        self.eval_n = 0;
+        self.eval_m = 0;
+        self.eval_found = 0;
+        self.eval_done = false;
+    }
+
+    // one even cycle of sprite evaluation, with the oam byte read on the cycle before
+    //
+    // until 8 sprites are found, every sprite's y is copied to secondary oam and the other 3 bytes
+    // follow when it's in range. after that, secondary oam is full and the ppu only looks for a 9th
+    // sprite to set the overflow flag, but it increments m along with n when a sprite isn't in
+    // range. it then reads the tile, attribute or x of the following sprites as their y, which
+    // both misses sprites that are on the scanline and finds ones that aren't
     pub fn evaluate_sprites(&mut self) {
-        let sprite_size = if self.flag_sprite_size { 16 } else { 8 };
-        let mut sprite_count = 0;
+        if self.eval_done {
+            return;
+        }
 
-        for i in 0..64 {
-            let y = self.oam_data[i * 4] as usize;
+        let value = self.oam_latch;
 
-            if self.scanline >= y && self.scanline - y < sprite_size {
-                for j in 0..4 {
-                    self.secondary_oam[sprite_count * 4 + j] = self.oam_data[i * 4 + j];
-                }
-                self.sprite_indexes[sprite_count] = i as u8;
-                sprite_count += 1;
+        if self.eval_found < 8 {
+            self.secondary_oam[self.eval_found * 4 + self.eval_m] = value;
+
+            if self.eval_m == 0 && !self.sprite_in_range(value) {
+                self.next_sprite();
+                return;
             }
 
-            if sprite_count == 8 {
-                // self.sprite_overflow = true;
-                break;
+            if self.eval_m == 0 {
+                self.next_sprite_indexes[self.eval_found] = self.eval_n as u8;
             }
+            self.eval_m += 1;
+            if self.eval_m == 4 {
+                self.eval_m = 0;
+                self.eval_found += 1;
+                self.next_sprite();
+            }
+        } else if self.sprite_in_range(value) {
+            // the 3 bytes after it are read as well, but nothing happens with them
+            self.sprite_overflow = true;
+            self.eval_done = true;
+        } else {
+            // the hardware bug, m should stay 0 here
+            self.eval_m = (self.eval_m + 1) & 3;
+            self.next_sprite();
         }
+    }
 
-        self.sprite_count = sprite_count;
+    fn next_sprite(&mut self) {
+        self.eval_n += 1;
+        if self.eval_n == 64 {
+            self.eval_n = 0;
+            self.eval_done = true;
+        }
     }
 
-    pub fn fetch_sprites(&mut self) {
-        for i in 0..self.sprite_count {
-            let y = self.secondary_oam[4 * i] as usize;
-            let sprite_tile_index = self.secondary_oam[4 * i + 1] as usize;
-            let sprite_attributes = self.secondary_oam[4 * i + 2];
-            let x = self.secondary_oam[4 * i + 3];
-
-            let flipped_vertically = sprite_attributes & (1 << 7) != 0;
-            let flipped_horizontally = sprite_attributes & (1 << 6) != 0;
-
-            // TODO - make scanline usize
-            let row = self.scanline as usize - y;
-
-            let mut address: usize = 0;
-            let sprite_size: usize = if self.flag_sprite_size { 16 } else { 8 };
-            if sprite_size == 8 {
-                address += if self.flag_sprite_table { 0x1000 } else { 0 };
-                address += sprite_tile_index * 16;
-
-                address += if !flipped_vertically {
-                    row
-                } else {
-                    sprite_size - 1 - row
-                };
-            } else {
-                address += if sprite_tile_index & 1 == 0 { 0x0 } else { 0x1000 };
-                address += (sprite_tile_index & 0xfffe) << 4;
+    // without the sprite limit, every sprite on the scanline is drawn straight from primary oam.
+    // the evaluation above still runs as usual, so the overflow flag games see doesn't change
+    pub fn find_all_sprites(&mut self) {
+        self.sprite_count = 0;
+        for n in 0..64 {
+            if self.sprite_in_range(self.oam_data[n * 4]) {
+                self.next_sprite_indexes[self.sprite_count] = n as u8;
+                self.sprite_count += 1;
+            }
+        }
+    }
 
-                let fine_y = if !flipped_vertically {
-                    row
-                } else {
-                    sprite_size - 1 - row
-                };
+    fn sprite_in_range(&self, y: u8) -> bool {
+        let sprite_size = if self.flag_sprite_size { 16 } else { 8 };
+        let y = y as usize;
 
-                address += fine_y;
+        self.scanline >= y && self.scanline - y < sprite_size
+    }
 
-                if fine_y > 7 {
-                    address += 8;
-                }
+    // dot 5 of a sprite slot. empty slots fetch tile $FF, which only the mapper sees
+    pub fn fetch_sprite_low(&mut self, slot: usize) {
+        if self.scanline < 240 && slot < self.sprite_count {
+            let address = self.sprite_pattern_address(slot);
+            self.sprite_low_byte = self.read(address, Fetch::Sprite);
+        } else {
+            let address = self.empty_sprite_address();
+            self.dummy_read(address, Fetch::Sprite);
+        }
+    }
+
+    // dot 7 of a sprite slot, which loads the sprite for the next scanline. without the sprite
+    // limit, the sprites after the 8th are all fetched along with the last slot
+    pub fn fetch_sprite_high(&mut self, slot: usize) {
+        if self.scanline < 240 && slot < self.sprite_count {
+            let address = self.sprite_pattern_address(slot);
+            let high_pattern_byte = self.read(address + 8, Fetch::Sprite);
+            self.load_sprite(slot, self.sprite_low_byte, high_pattern_byte);
+        } else {
+            let address = self.empty_sprite_address();
+            self.dummy_read(address + 8, Fetch::Sprite);
+        }
+
+        if self.scanline < 240 && slot == 7 {
+            for i in 8..self.sprite_count {
+                let address = self.sprite_pattern_address(i);
+                let low_pattern_byte = self.read(address, Fetch::Sprite);
+                let high_pattern_byte = self.read(address + 8, Fetch::Sprite);
+                self.load_sprite(i, low_pattern_byte, high_pattern_byte);
             }
+        }
+    }
 
-            let low_pattern_byte = self.read(address);
-            let high_pattern_byte = self.read(address + 8);
+    // the 4 oam bytes of the sprite in a slot, straight from primary oam without the sprite limit
+    fn sprite_oam(&self, slot: usize) -> [u8; 4] {
+        let (oam, offset) = if self.sprite_limit {
+            (&self.secondary_oam[..], 4 * slot)
+        } else {
+            (&self.oam_data[..], 4 * self.sprite_indexes[slot] as usize)
+        };
 
-            let mut shift_registers: (u8, u8) = (0, 0);
+        [oam[offset], oam[offset + 1], oam[offset + 2], oam[offset + 3]]
+    }
 
-            // fill out sprite shift register by looping through each bit
-            //
-            // if flipped horizontally, the bits are mirrored by the nibble
-            // e.g. 0b1001_0110 => 0b0110_1001
-            for j in 0..8 {
-                let mut low_bits = low_pattern_byte & (1 << j);
-                let mut high_bits = high_pattern_byte & (1 << j);
+    // address of the low pattern byte of the sprite's row on the next scanline
+    fn sprite_pattern_address(&self, slot: usize) -> usize {
+        let [y, sprite_tile_index, sprite_attributes, _] = self.sprite_oam(slot);
+        let y = y as usize;
+        let sprite_tile_index = sprite_tile_index as usize;
 
-                if flipped_horizontally {
-                    // mirror the bits by the nibble
-                    // e.g. 0b0001_0000 => 0b0000_1000
-                    low_bits = (low_bits >> j) << (7 - j);
-                    high_bits = (high_bits >> j) << (7 - j);
-                }
+        let flipped_vertically = sprite_attributes & (1 << 7) != 0;
+
+        // TODO - make scanline usize
+        let row = self.scanline as usize - y;
+
+        let mut address: usize = 0;
+        let sprite_size: usize = if self.flag_sprite_size { 16 } else { 8 };
+        if sprite_size == 8 {
+            address += if self.flag_sprite_table { 0x1000 } else { 0 };
+            address += sprite_tile_index * 16;
+
+            address += if !flipped_vertically {
+                row
+            } else {
+                sprite_size - 1 - row
+            };
+        } else {
+            address += if sprite_tile_index & 1 == 0 { 0x0 } else { 0x1000 };
+            address += (sprite_tile_index & 0xfffe) << 4;
+
+            let fine_y = if !flipped_vertically {
+                row
+            } else {
+                sprite_size - 1 - row
+            };
+
+            address += fine_y;
+
+            if fine_y > 7 {
+                address += 8;
+            }
+        }
+
+        address
+    }
+
+    // secondary oam is left filled with $FF past the sprites that were found
+    fn empty_sprite_address(&self) -> usize {
+        if self.flag_sprite_size {
+            0x1000 | 0xfe << 4
+        } else if self.flag_sprite_table {
+            0x1000 | 0xff << 4
+        } else {
+            0xff << 4
+        }
+    }
 
-                shift_registers.0 |= low_bits;
-                shift_registers.1 |= high_bits;
+    fn load_sprite(&mut self, slot: usize, low_pattern_byte: u8, high_pattern_byte: u8) {
+        let [_, _, sprite_attributes, x] = self.sprite_oam(slot);
+        let flipped_horizontally = sprite_attributes & (1 << 6) != 0;
+
+        let mut shift_registers: (u8, u8) = (0, 0);
+
+        // fill out sprite shift register by looping through each bit
+        //
+        // if flipped horizontally, the bits are mirrored by the nibble
+        // e.g. 0b1001_0110 => 0b0110_1001
+        for j in 0..8 {
+            let mut low_bits = low_pattern_byte & (1 << j);
+            let mut high_bits = high_pattern_byte & (1 << j);
+
+            if flipped_horizontally {
+                // mirror the bits by the nibble
+                // e.g. 0b0001_0000 => 0b0000_1000
+                low_bits = (low_bits >> j) << (7 - j);
+                high_bits = (high_bits >> j) << (7 - j);
             }
 
-            self.sprite_pattern_shift_regs[i] = shift_registers;
-            self.sprite_attribute_latches[i] = sprite_attributes;
-            self.sprite_positions[i] = x;
+            shift_registers.0 |= low_bits;
+            shift_registers.1 |= high_bits;
         }
+
+        self.sprite_pattern_shift_regs[slot] = shift_registers;
+        self.sprite_attribute_latches[slot] = sprite_attributes;
+        self.sprite_positions[slot] = x;
     }
 
     pub fn render_pixel(&mut self) -> (usize, usize, Color) {
@@ -240,12 +351,12 @@ impl PPU {
         // these are conveniently stored in bits 9-5 and 4-0 respectively in the v register,
         // so we can simply mask off the unneeded bits and or it with the offset to get the address
         let address = 0x2000 | (self.v & 0x0fff) as usize;
-        self.nametable_byte = self.read(address);
+        self.nametable_byte = self.read(address, Fetch::Nametable);
     }
 
     pub fn fetch_attribute_table_byte(&mut self) {
         let address = (0x23C0 | (self.v & 0x0C00) | ((self.v >> 4) & 0x38) | ((self.v >> 2) & 0x07)) as usize;
-        let byte = self.read(address as usize);
+        let byte = self.read(address as usize, Fetch::Attribute);
 
         let coarse_x =  self.v & 0x1f;
         let coarse_y = (self.v & 0x3e0) >> 5;
@@ -268,7 +379,7 @@ impl PPU {
 
         let address = (table_base + tile + fine_y) as usize;
 
-        self.low_tile_byte = self.read(address);
+        self.low_tile_byte = self.read(address, Fetch::Background);
     }
 
     pub fn fetch_high_tile_byte(&mut self) {
@@ -278,7 +389,7 @@ impl PPU {
 
         let address = (table_base + tile + fine_y) as usize;
 
-        self.high_tile_byte = self.read(address + 8);
+        self.high_tile_byte = self.read(address + 8, Fetch::Background);
     }
 
     pub fn update_shift_registers(&mut self) {
@@ -0,0 +1,71 @@
+// the timing of the different consoles
+//
+// https://wiki.nesdev.com/w/index.php/Cycle_reference_chart
+//
+// PAL consoles run the cpu slower and the ppu at 3.2 dots per cpu cycle, with 50 extra scanlines
+// of vblank. the Dendy famiclone keeps the NTSC ratio of 3 with the PAL frame, and starts vblank
+// 50 scanlines later instead so games written for NTSC have about the same vblank time
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Region {
+    NTSC,
+    PAL,
+    Dendy
+}
+
+impl Region {
+    // --region on the command line
+    pub fn from_name(name: &str) -> Option<Region> {
+        match name.to_lowercase().as_str() {
+            "ntsc" => Some(Region::NTSC),
+            "pal" => Some(Region::PAL),
+            "dendy" => Some(Region::Dendy),
+            _ => None
+        }
+    }
+
+    pub fn cpu_frequency(self) -> f64 {
+        match self {
+            Region::NTSC => 1_789_773.0,
+            Region::PAL => 1_662_607.0,
+            Region::Dendy => 1_773_448.0
+        }
+    }
+
+    // ppu dots per cpu cycle as a fraction, (dots, cpu cycles)
+    pub fn ppu_clock_ratio(self) -> (u64, u64) {
+        match self {
+            Region::PAL => (16, 5),
+            _ => (3, 1)
+        }
+    }
+
+    pub fn scanlines(self) -> usize {
+        match self {
+            Region::NTSC => 262,
+            _ => 312
+        }
+    }
+
+    // the last scanline of the frame, which fetches the first tiles of the next one
+    pub fn pre_render_scanline(self) -> usize {
+        self.scanlines() - 1
+    }
+
+    pub fn vblank_scanline(self) -> usize {
+        match self {
+            Region::Dendy => 291,
+            _ => 241
+        }
+    }
+
+    // only the NTSC ppu skips a dot of the pre-render scanline on odd frames
+    pub fn skips_odd_frame_dot(self) -> bool {
+        self == Region::NTSC
+    }
+
+    // ~60.1 Hz for NTSC, ~50 Hz for the others
+    pub fn frame_rate(self) -> f64 {
+        let (dots, cycles) = self.ppu_clock_ratio();
+        self.cpu_frequency() * dots as f64 / cycles as f64 / (341 * self.scanlines()) as f64
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// where a label points to
+//
+// labels in rom are keyed by their offset into prg rom rather than by cpu address, since the same
+// cpu address can hold different banks over time. a label in bank 3 of a UxROM game only matches
+// while bank 3 is actually switched in
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Location {
+    PrgRom(usize), // offset into prg rom (16 KiB bank = offset / 0x4000)
+    Cpu(u16) // fixed cpu address, e.g. ram or a register
+}
+
+pub struct SymbolTable {
+    labels: HashMap<Location, String>,
+    locations: HashMap<String, Location>
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            labels: HashMap::new(),
+            locations: HashMap::new()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    // load a label file, the format is picked by the file extension
+    //
+    // ld65 debug info (.dbg), FCEUX name lists (.nl) and Mesen label files (.mlb) are supported
+    pub fn load(&mut self, file_path: &str) -> Result<(), String> {
+        let text = match fs::read_to_string(file_path) {
+            Ok(t) => t,
+            Err(e) => return Err(format!("Cannot load symbols! {}: {}", file_path, e))
+        };
+
+        let path = Path::new(file_path);
+        let count = self.labels.len();
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("dbg") => self.load_ld65(&text),
+            Some("nl") => self.load_fceux(&text, fceux_bank(path)),
+            Some("mlb") => self.load_mesen(&text),
+            _ => return Err(format!("Unknown symbol file format! {}", file_path))
+        }
+
+        println!("Loaded {} labels from: {}", self.labels.len() - count, file_path);
+        Ok(())
+    }
+
+    pub fn add(&mut self, location: Location, name: &str) {
+        // keep the first name for a location, and the first location for a name
+        self.labels.entry(location).or_insert_with(|| String::from(name));
+        self.locations.entry(String::from(name)).or_insert(location);
+    }
+
+    // the label at a cpu address, rom labels take precedence over fixed cpu addresses
+    //
+    // `prg_offset` is where the address currently lands in prg rom, if anywhere
+    pub fn label(&self, address: u16, prg_offset: Option<usize>) -> Option<&str> {
+        prg_offset
+            .and_then(|offset| self.labels.get(&Location::PrgRom(offset)))
+            .or_else(|| self.labels.get(&Location::Cpu(address)))
+            .map(|label| label.as_str())
+    }
+
+    pub fn location(&self, name: &str) -> Option<Location> {
+        self.locations.get(name).copied()
+    }
+
+    // ld65 --dbgfile output
+    //
+    // every line is a record type followed by a tab and comma separated key=value pairs:
+    // seg  id=2,name="CODE",start=0x00C000,size=0x0123,addrsize=absolute,type=ro,oname="game.nes",ooffs=16
+    // sym  id=5,name="main_loop",addrsize=absolute,scope=0,def=12,ref=14,val=0xC0A4,seg=2,type=lab
+    //
+    // segments written to the output file carry their offset into it, which is used to turn a
+    // symbol's value into a prg rom offset. segments without one (zeropage, bss) live in ram
+    fn load_ld65(&mut self, text: &str) {
+        let records: Vec<(&str, HashMap<&str, &str>)> = text
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, '\t');
+                let kind = parts.next()?;
+                let fields = parts.next()?
+                    .split(',')
+                    .filter_map(|field| {
+                        let mut pair = field.splitn(2, '=');
+                        Some((pair.next()?, pair.next()?.trim_matches('"')))
+                    })
+                    .collect();
+                Some((kind, fields))
+            })
+            .collect();
+
+        // segment id => (start address, offset into the output file)
+        let mut segments: HashMap<&str, (usize, Option<usize>)> = HashMap::new();
+        for (kind, fields) in records.iter() {
+            if *kind == "seg" {
+                if let (Some(id), Some(start)) = (fields.get("id"), fields.get("start").and_then(|s| parse_number(s))) {
+                    let file_offset = fields.get("ooffs").and_then(|s| parse_number(s));
+                    segments.insert(id, (start, file_offset));
+                }
+            }
+        }
+
+        for (kind, fields) in records.iter() {
+            // only plain labels, cheap local labels (@loop) have a parent and would just be noise
+            if *kind != "sym" || fields.get("type") != Some(&"lab") || fields.contains_key("parent") {
+                continue;
+            }
+
+            let name = match fields.get("name") {
+                Some(n) => n,
+                None => continue
+            };
+
+            let value = match fields.get("val").and_then(|s| parse_number(s)) {
+                Some(v) => v,
+                None => continue
+            };
+
+            let segment = fields.get("seg").and_then(|id| segments.get(id));
+            let location = match segment {
+                // skip the 16 byte iNES header that ld65 writes in front of prg rom
+                Some((start, Some(file_offset))) if *file_offset >= 0x10 && value >= *start => {
+                    Location::PrgRom(file_offset - 0x10 + (value - start))
+                },
+                _ => Location::Cpu(value as u16)
+            };
+
+            self.add(location, name);
+        }
+    }
+
+    // FCEUX name lists, one file per 16 KiB prg bank (game.nes.0.nl, game.nes.1.nl, ...) and one
+    // for ram (game.nes.ram.nl)
+    //
+    // $C0A4#main_loop#optional comment
+    // $0200/100#oam_buffer#arrays have a size after the address
+    fn load_fceux(&mut self, text: &str, bank: Option<usize>) {
+        for line in text.lines() {
+            let mut parts = line.splitn(3, '#');
+            let address = match parts.next() {
+                Some(a) if a.starts_with('$') => a[1..].split('/').next().unwrap_or(""),
+                _ => continue
+            };
+            let name = parts.next().unwrap_or("").trim();
+
+            if let (Ok(address), false) = (u16::from_str_radix(address, 16), name.is_empty()) {
+                let location = match bank {
+                    Some(bank) if address >= 0x8000 => Location::PrgRom(bank * 0x4000 + (address as usize & 0x3fff)),
+                    _ => Location::Cpu(address)
+                };
+
+                self.add(location, name);
+            }
+        }
+    }
+
+    // Mesen label files, both the Mesen 1 single letter memory types and the Mesen 2 names
+    //
+    // P:C0A4:main_loop:comment      NesPrgRom:C0A4:main_loop
+    // R:0010:player_x               NesInternalRam:0010:player_x
+    // an address range (0200-02FF) labels its first byte
+    fn load_mesen(&mut self, text: &str) {
+        for line in text.lines() {
+            let mut parts = line.splitn(4, ':');
+            let (kind, address, name) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(k), Some(a), Some(n)) => (k, a.split('-').next().unwrap_or(""), n.trim()),
+                _ => continue
+            };
+
+            let address = match usize::from_str_radix(address, 16) {
+                Ok(a) => a,
+                Err(_) => continue
+            };
+
+            if name.is_empty() {
+                continue;
+            }
+
+            let location = match kind {
+                "P" | "NesPrgRom" => Location::PrgRom(address),
+                "R" | "NesInternalRam" => Location::Cpu(address as u16),
+                "S" | "W" | "NesSaveRam" | "NesWorkRam" => Location::Cpu(0x6000 + address as u16),
+                "G" | "NesMemory" => Location::Cpu(address as u16),
+                _ => continue
+            };
+
+            self.add(location, name);
+        }
+    }
+}
+
+// the bank an FCEUX name list belongs to, taken from its file name (<rom>.<bank>.nl), None for
+// the ram file (<rom>.ram.nl) or any other name
+fn fceux_bank(path: &Path) -> Option<usize> {
+    let stem = path.file_stem()?.to_str()?;
+    let (_, bank) = stem.rsplit_once('.')?;
+    usize::from_str_radix(bank, 16).ok()
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    if let Some(hex) = text.strip_prefix("0x") {
+        usize::from_str_radix(hex, 16).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fceux_bank_needs_a_bank_suffix() {
+        assert_eq!(fceux_bank(Path::new("game.nes.0.nl")), Some(0));
+        assert_eq!(fceux_bank(Path::new("game.nes.1f.nl")), Some(0x1f));
+        assert_eq!(fceux_bank(Path::new("game.nes.ram.nl")), None);
+        assert_eq!(fceux_bank(Path::new("cafe.nl")), None);
+    }
+}